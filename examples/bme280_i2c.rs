@@ -7,6 +7,7 @@ use stm32h7x3_hal::{
     prelude::*,
     stm32h7x3,
     delay::Delay,
+    gpio::AF4,
     i2c::I2c,
 };
 
@@ -27,8 +28,8 @@ fn main() -> ! {
     let gpiob = p.GPIOB.split(&mut rcc.ahb4);
 
     // Configure the SCL and the SDA pin for our I2C bus
-    let scl = gpiob.pb8.into_alternate_af4().set_open_drain();
-    let sda = gpiob.pb9.into_alternate_af4().set_open_drain();
+    let scl = gpiob.pb8.into_alternate_open_drain::<AF4>();
+    let sda = gpiob.pb9.into_alternate_open_drain::<AF4>();
 
     let i2c_bus = I2c::i2c1(
         p.I2C1,
@@ -36,7 +37,8 @@ fn main() -> ! {
         100.khz(),
         clocks,
         &mut rcc.apb1l,
-    );
+    )
+    .unwrap();
 
     // instantiate and initialize the bme280 chip
     let mut bme280 = BME280::new_primary(i2c_bus, Delay::new(cp.SYST, clocks));
@@ -49,9 +49,8 @@ fn main() -> ! {
     // let rx = gpioa.pa3;
 
     // USART3
-    // Configure pb10 as a push_pull output, this will be the tx pin
-    let tx = gpiob.pb10.into_push_pull_output().into_alternate_af7();
-    // Take ownership over pb11
+    // Configure pb10 as the push pull tx pin and pb11 as the rx pin, both in alternate function 7
+    let tx = gpiob.pb10.into_alternate_af7();
     let rx = gpiob.pb11.into_alternate_af7();
 
     // Set up the usart device. Taks ownership over the USART register and tx/rx pins. The rest of
@@ -73,7 +72,7 @@ fn main() -> ! {
     block!(tx.write(sent)).ok();
 
     // Read the byte that was just sent. Blocks until the read is complete
-    let received = block!(rx.read()).unwrap();
+    let received: u8 = block!(rx.read()).unwrap();
 
     // Since we have connected tx and rx, the byte we sent should be the one we received
     assert_eq!(received, sent);
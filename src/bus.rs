@@ -0,0 +1,214 @@
+//! Sharing a bus between device drivers
+//!
+//! A `BusManager` takes ownership of an I2C or SPI peripheral and hands out proxies that
+//! implement the blocking traits of embedded-hal, one for every device driver. Every
+//! operation locks the bus, so operations of different devices never interleave.
+//!
+//! `LocalBusManager` locks the bus with a `RefCell` and can only be used from a single
+//! context. `SharedBusManager` locks the bus in a critical section, so devices can be used
+//! from interrupt handlers as well. The manager has to outlive the proxies, e.g. by placing
+//! it in a `static` with `cortex_m::singleton!`.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{self, Mutex};
+use hal::blocking::i2c::{Read, Write, WriteRead};
+use hal::blocking::spi;
+use hal::digital::OutputPin;
+
+/// A mutex the bus is locked with while a device accesses it
+pub trait BusMutex {
+    /// The shared bus
+    type Bus;
+
+    /// Creates the mutex around `bus`
+    fn create(bus: Self::Bus) -> Self;
+
+    /// Locks the bus while `f` accesses it
+    fn lock<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Self::Bus) -> R;
+}
+
+impl<BUS> BusMutex for RefCell<BUS> {
+    type Bus = BUS;
+
+    fn create(bus: BUS) -> Self {
+        RefCell::new(bus)
+    }
+
+    /// Panics if the bus is already locked, i.e. when `f` accesses the bus itself
+    fn lock<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut BUS) -> R,
+    {
+        f(&mut self.borrow_mut())
+    }
+}
+
+impl<BUS> BusMutex for Mutex<RefCell<BUS>> {
+    type Bus = BUS;
+
+    fn create(bus: BUS) -> Self {
+        Mutex::new(RefCell::new(bus))
+    }
+
+    fn lock<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut BUS) -> R,
+    {
+        interrupt::free(|cs| f(&mut self.borrow(cs).borrow_mut()))
+    }
+}
+
+/// Owner of a shared bus, locked with `M`
+pub struct BusManager<M> {
+    mutex: M,
+}
+
+/// Bus manager for a single context
+pub type LocalBusManager<BUS> = BusManager<RefCell<BUS>>;
+
+/// Bus manager that can be shared with interrupt handlers
+pub type SharedBusManager<BUS> = BusManager<Mutex<RefCell<BUS>>>;
+
+impl<M: BusMutex> BusManager<M> {
+    /// Takes ownership of `bus`
+    pub fn new(bus: M::Bus) -> Self {
+        BusManager { mutex: M::create(bus) }
+    }
+
+    /// Returns a proxy for an I2C device on the bus
+    pub fn acquire_i2c(&self) -> I2cDevice<'_, M> {
+        I2cDevice { mutex: &self.mutex }
+    }
+
+    /// Returns a proxy for an SPI device on the bus, selected by the active low `cs`
+    ///
+    /// `cs` is driven high, it is only driven low during the operations of this device.
+    pub fn acquire_spi<CS>(&self, mut cs: CS) -> SpiDevice<'_, M, CS>
+    where
+        CS: OutputPin,
+    {
+        cs.set_high();
+
+        SpiDevice { mutex: &self.mutex, cs }
+    }
+}
+
+/// Proxy for an I2C device on a shared bus
+pub struct I2cDevice<'a, M> {
+    mutex: &'a M,
+}
+
+impl<'a, M> Write for I2cDevice<'a, M>
+where
+    M: BusMutex,
+    M::Bus: Write,
+{
+    type Error = <M::Bus as Write>::Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.mutex.lock(|bus| bus.write(addr, bytes))
+    }
+}
+
+impl<'a, M> Read for I2cDevice<'a, M>
+where
+    M: BusMutex,
+    M::Bus: Read,
+{
+    type Error = <M::Bus as Read>::Error;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.mutex.lock(|bus| bus.read(addr, buffer))
+    }
+}
+
+impl<'a, M> WriteRead for I2cDevice<'a, M>
+where
+    M: BusMutex,
+    M::Bus: WriteRead,
+{
+    type Error = <M::Bus as WriteRead>::Error;
+
+    fn write_read(
+        &mut self,
+        addr: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.mutex.lock(|bus| bus.write_read(addr, bytes, buffer))
+    }
+}
+
+/// Proxy for an SPI device on a shared bus with its own chip select
+///
+/// The chip select is driven low for the duration of every operation, while the bus is locked.
+pub struct SpiDevice<'a, M, CS> {
+    mutex: &'a M,
+    cs: CS,
+}
+
+impl<'a, M, CS> SpiDevice<'a, M, CS> {
+    /// Releases the chip select pin
+    pub fn free(self) -> CS {
+        self.cs
+    }
+}
+
+/// Implements the blocking SPI traits for every word type
+///
+/// embedded-hal implements `Transfer<W>` and `Write<W>` for every type implementing its
+/// `transfer::Default<W>` and `write::Default<W>` markers, so an implementation for
+/// `SpiDevice` that is generic over `W` is rejected as conflicting.
+macro_rules! spi_device {
+    ($($WORD:ty),+) => {
+        $(
+        impl<'a, M, CS> spi::Transfer<$WORD> for SpiDevice<'a, M, CS>
+        where
+            M: BusMutex,
+            M::Bus: spi::Transfer<$WORD>,
+            CS: OutputPin,
+        {
+            type Error = <M::Bus as spi::Transfer<$WORD>>::Error;
+
+            fn transfer<'w>(
+                &mut self,
+                words: &'w mut [$WORD],
+            ) -> Result<&'w [$WORD], Self::Error> {
+                let cs = &mut self.cs;
+                self.mutex.lock(move |bus| {
+                    cs.set_low();
+                    let result = bus.transfer(words);
+                    cs.set_high();
+
+                    result
+                })
+            }
+        }
+
+        impl<'a, M, CS> spi::Write<$WORD> for SpiDevice<'a, M, CS>
+        where
+            M: BusMutex,
+            M::Bus: spi::Write<$WORD>,
+            CS: OutputPin,
+        {
+            type Error = <M::Bus as spi::Write<$WORD>>::Error;
+
+            fn write(&mut self, words: &[$WORD]) -> Result<(), Self::Error> {
+                let cs = &mut self.cs;
+                self.mutex.lock(|bus| {
+                    cs.set_low();
+                    let result = bus.write(words);
+                    cs.set_high();
+
+                    result
+                })
+            }
+        }
+        )+
+    };
+}
+
+spi_device!(u8, u16, u32);
@@ -0,0 +1,315 @@
+//! Direct Memory Access implementation
+//!
+//! Streams of DMA1 and DMA2, each connected to its own DMAMUX1 channel which selects the
+//! peripheral request that drives it. DMA1 and DMA2 can not access the DTCM, so buffers
+//! have to be placed in the AXI SRAM or SRAM1 to SRAM3. If the data cache is enabled the
+//! buffers have to be cleaned or invalidated around a transfer as well.
+
+use crate::rcc::AHB1;
+
+/// Extension trait to split a DMA controller into independent streams
+pub trait DmaExt {
+    /// The streams to split the DMA controller into
+    type Streams;
+
+    /// Enables the DMA controller and splits it into its streams
+    fn split(self, ahb1: &mut AHB1) -> Self::Streams;
+}
+
+/// Direction of a transfer
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// From the peripheral register to memory
+    PeripheralToMemory,
+    /// From memory to the peripheral register
+    MemoryToPeripheral,
+}
+
+/// Size of a single data item
+#[derive(Clone, Copy, PartialEq)]
+pub enum Size {
+    /// 8 bits
+    Byte,
+    /// 16 bits
+    HalfWord,
+    /// 32 bits
+    Word,
+}
+
+impl Size {
+    fn bits(self) -> u8 {
+        match self {
+            Size::Byte => 0b00,
+            Size::HalfWord => 0b01,
+            Size::Word => 0b10,
+        }
+    }
+}
+
+/// Interrupt events
+pub enum Event {
+    /// Half of the data items were transferred
+    HalfTransfer,
+    /// All data items were transferred
+    TransferComplete,
+    /// A bus error occurred
+    TransferError,
+}
+
+/// Configuration of a stream
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// DMAMUX1 request line that drives the stream
+    pub request: u8,
+    /// Direction of the transfer
+    pub direction: Direction,
+    /// Address of the peripheral register
+    pub peripheral: u32,
+    /// Address of the memory buffer
+    pub memory: u32,
+    /// Number of data items to transfer
+    pub len: u16,
+    /// Size of the data items
+    pub size: Size,
+    /// Advance the memory address after every data item
+    pub memory_increment: bool,
+    /// Restart at the beginning of the buffer once the transfer is complete
+    pub circular: bool,
+    /// Address of a second memory buffer, alternating with `memory` in double buffer mode
+    ///
+    /// Double buffer mode implies circular mode.
+    pub memory1: Option<u32>,
+}
+
+/// A DMA stream
+pub trait Stream {
+    /// Configures the stream, it has to be disabled
+    ///
+    /// # Safety
+    ///
+    /// The DMA accesses the addresses of `config` until the stream is disabled. The caller
+    /// has to make sure `len` data items of `size` are valid at `memory`, and at `memory1`
+    /// in double buffer mode, for that long, and that `peripheral` is a register the
+    /// request line can drive.
+    unsafe fn configure(&mut self, config: &Config);
+
+    /// Starts the transfer
+    fn enable(&mut self);
+
+    /// Stops the transfer, waiting until the current data item is done
+    fn disable(&mut self);
+
+    /// Returns whether a transfer is ongoing
+    fn is_enabled(&self) -> bool;
+
+    /// Returns the number of data items left to transfer
+    fn remaining(&self) -> u16;
+
+    /// Returns whether half of the data items were transferred
+    fn is_half_complete(&self) -> bool;
+
+    /// Returns whether all data items were transferred
+    fn is_complete(&self) -> bool;
+
+    /// Returns the memory buffer accessed by the stream in double buffer mode, 0 or 1
+    fn current_memory(&self) -> u8;
+
+    /// Returns whether a transfer or direct mode error occurred
+    fn has_error(&self) -> bool;
+
+    /// Clears all interrupt flags of the stream
+    fn clear_flags(&mut self);
+
+    /// Starts listening for an interrupt event
+    fn listen(&mut self, event: Event);
+
+    /// Stops listening for an interrupt event
+    fn unlisten(&mut self, event: Event);
+}
+
+// Interrupt flags relative to the offset of a stream in the status registers
+const FEIF: u32 = 1 << 0;
+const DMEIF: u32 = 1 << 2;
+const TEIF: u32 = 1 << 3;
+const HTIF: u32 = 1 << 4;
+const TCIF: u32 = 1 << 5;
+
+macro_rules! dma {
+    ($DMAX:ident, $dmax:ident, $dmaxen:ident, $dmaxrst:ident, [
+        $($StreamX:ident: ($streamx:ident, $sxcr:ident, $sxndtr:ident, $sxpar:ident, $sxm0ar:ident, $sxm1ar:ident,
+                           $sxfcr:ident,
+                           $xisr:ident, $xifcr:ident, $offset:expr, $cxcr:ident),)+
+    ]) => {
+        /// DMA controller
+        pub mod $dmax {
+            use stm32h7::stm32h7x3::{$DMAX, DMAMUX1};
+            use super::*;
+
+            /// DMA streams
+            pub struct Streams {
+                $(pub $streamx: $StreamX,)+
+            }
+
+            impl DmaExt for $DMAX {
+                type Streams = Streams;
+
+                fn split(self, ahb1: &mut AHB1) -> Streams {
+                    ahb1.enr().modify(|_, w| w.$dmaxen().set_bit());
+                    ahb1.rstr().modify(|_, w| w.$dmaxrst().set_bit());
+                    ahb1.rstr().modify(|_, w| w.$dmaxrst().clear_bit());
+
+                    Streams {
+                        $($streamx: $StreamX { _0: () },)+
+                    }
+                }
+            }
+
+            $(
+                /// DMA stream
+                pub struct $StreamX {
+                    _0: (),
+                }
+
+                impl Stream for $StreamX {
+                    unsafe fn configure(&mut self, config: &Config) {
+                        // NOTE(unsafe) this stream owns its registers and DMAMUX1 channel
+                        let dma = &*$DMAX::ptr();
+                        let dmamux = &*DMAMUX1::ptr();
+
+                        dmamux.$cxcr.write(|w| w.dmareq_id().bits(config.request));
+
+                        dma.$sxpar.write(|w| w.pa().bits(config.peripheral));
+                        dma.$sxm0ar.write(|w| w.m0a().bits(config.memory));
+                        if let Some(memory1) = config.memory1 {
+                            dma.$sxm1ar.write(|w| w.m1a().bits(memory1));
+                        }
+                        dma.$sxndtr.write(|w| w.ndt().bits(config.len));
+                        // Direct mode, the data item size is the same on both sides
+                        dma.$sxfcr.write(|w| w.dmdis().clear_bit());
+                        dma.$sxcr.write(|w| {
+                            w.dir()
+                                .bits(match config.direction {
+                                    Direction::PeripheralToMemory => 0b00,
+                                    Direction::MemoryToPeripheral => 0b01,
+                                })
+                                .psize()
+                                .bits(config.size.bits())
+                                .msize()
+                                .bits(config.size.bits())
+                                .pinc()
+                                .clear_bit()
+                                .minc()
+                                .bit(config.memory_increment)
+                                .circ()
+                                .bit(config.circular || config.memory1.is_some())
+                                .dbm()
+                                .bit(config.memory1.is_some())
+                                .ct()
+                                .clear_bit()
+                        });
+                    }
+
+                    fn enable(&mut self) {
+                        self.clear_flags();
+                        // NOTE(unsafe) this stream owns its registers
+                        unsafe { (*$DMAX::ptr()).$sxcr.modify(|_, w| w.en().set_bit()) }
+                    }
+
+                    fn disable(&mut self) {
+                        // NOTE(unsafe) this stream owns its registers
+                        let dma = unsafe { &*$DMAX::ptr() };
+                        dma.$sxcr.modify(|_, w| w.en().clear_bit());
+                        while dma.$sxcr.read().en().bit_is_set() {}
+                    }
+
+                    fn is_enabled(&self) -> bool {
+                        // NOTE(unsafe) atomic read with no side effects
+                        unsafe { (*$DMAX::ptr()).$sxcr.read().en().bit_is_set() }
+                    }
+
+                    fn remaining(&self) -> u16 {
+                        // NOTE(unsafe) atomic read with no side effects
+                        unsafe { (*$DMAX::ptr()).$sxndtr.read().ndt().bits() }
+                    }
+
+                    fn current_memory(&self) -> u8 {
+                        // NOTE(unsafe) atomic read with no side effects
+                        unsafe { (*$DMAX::ptr()).$sxcr.read().ct().bit() as u8 }
+                    }
+
+                    fn is_half_complete(&self) -> bool {
+                        self.flags() & HTIF != 0
+                    }
+
+                    fn is_complete(&self) -> bool {
+                        self.flags() & TCIF != 0
+                    }
+
+                    fn has_error(&self) -> bool {
+                        self.flags() & (TEIF | DMEIF) != 0
+                    }
+
+                    fn clear_flags(&mut self) {
+                        let flags = (FEIF | DMEIF | TEIF | HTIF | TCIF) << $offset;
+                        // NOTE(unsafe) write to a stateless register, only touching our flags
+                        unsafe { (*$DMAX::ptr()).$xifcr.write(|w| w.bits(flags)) }
+                    }
+
+                    fn listen(&mut self, event: Event) {
+                        // NOTE(unsafe) this stream owns its registers
+                        let dma = unsafe { &*$DMAX::ptr() };
+                        match event {
+                            Event::HalfTransfer => dma.$sxcr.modify(|_, w| w.htie().set_bit()),
+                            Event::TransferComplete => dma.$sxcr.modify(|_, w| w.tcie().set_bit()),
+                            Event::TransferError => {
+                                dma.$sxcr.modify(|_, w| w.teie().set_bit().dmeie().set_bit())
+                            }
+                        }
+                    }
+
+                    fn unlisten(&mut self, event: Event) {
+                        // NOTE(unsafe) this stream owns its registers
+                        let dma = unsafe { &*$DMAX::ptr() };
+                        match event {
+                            Event::HalfTransfer => dma.$sxcr.modify(|_, w| w.htie().clear_bit()),
+                            Event::TransferComplete => dma.$sxcr.modify(|_, w| w.tcie().clear_bit()),
+                            Event::TransferError => {
+                                dma.$sxcr.modify(|_, w| w.teie().clear_bit().dmeie().clear_bit())
+                            }
+                        }
+                    }
+                }
+
+                impl $StreamX {
+                    /// Returns the interrupt flags of this stream
+                    fn flags(&self) -> u32 {
+                        // NOTE(unsafe) atomic read with no side effects
+                        unsafe { ((*$DMAX::ptr()).$xisr.read().bits() >> $offset) & 0x3f }
+                    }
+                }
+            )+
+        }
+    };
+}
+
+dma!(DMA1, dma1, dma1en, dma1rst, [
+    Stream0: (stream0, s0cr, s0ndtr, s0par, s0m0ar, s0m1ar, s0fcr, lisr, lifcr, 0, dmamux1_c0cr),
+    Stream1: (stream1, s1cr, s1ndtr, s1par, s1m0ar, s1m1ar, s1fcr, lisr, lifcr, 6, dmamux1_c1cr),
+    Stream2: (stream2, s2cr, s2ndtr, s2par, s2m0ar, s2m1ar, s2fcr, lisr, lifcr, 16, dmamux1_c2cr),
+    Stream3: (stream3, s3cr, s3ndtr, s3par, s3m0ar, s3m1ar, s3fcr, lisr, lifcr, 22, dmamux1_c3cr),
+    Stream4: (stream4, s4cr, s4ndtr, s4par, s4m0ar, s4m1ar, s4fcr, hisr, hifcr, 0, dmamux1_c4cr),
+    Stream5: (stream5, s5cr, s5ndtr, s5par, s5m0ar, s5m1ar, s5fcr, hisr, hifcr, 6, dmamux1_c5cr),
+    Stream6: (stream6, s6cr, s6ndtr, s6par, s6m0ar, s6m1ar, s6fcr, hisr, hifcr, 16, dmamux1_c6cr),
+    Stream7: (stream7, s7cr, s7ndtr, s7par, s7m0ar, s7m1ar, s7fcr, hisr, hifcr, 22, dmamux1_c7cr),
+]);
+
+dma!(DMA2, dma2, dma2en, dma2rst, [
+    Stream0: (stream0, s0cr, s0ndtr, s0par, s0m0ar, s0m1ar, s0fcr, lisr, lifcr, 0, dmamux1_c8cr),
+    Stream1: (stream1, s1cr, s1ndtr, s1par, s1m0ar, s1m1ar, s1fcr, lisr, lifcr, 6, dmamux1_c9cr),
+    Stream2: (stream2, s2cr, s2ndtr, s2par, s2m0ar, s2m1ar, s2fcr, lisr, lifcr, 16, dmamux1_c10cr),
+    Stream3: (stream3, s3cr, s3ndtr, s3par, s3m0ar, s3m1ar, s3fcr, lisr, lifcr, 22, dmamux1_c11cr),
+    Stream4: (stream4, s4cr, s4ndtr, s4par, s4m0ar, s4m1ar, s4fcr, hisr, hifcr, 0, dmamux1_c12cr),
+    Stream5: (stream5, s5cr, s5ndtr, s5par, s5m0ar, s5m1ar, s5fcr, hisr, hifcr, 6, dmamux1_c13cr),
+    Stream6: (stream6, s6cr, s6ndtr, s6par, s6m0ar, s6m1ar, s6fcr, hisr, hifcr, 16, dmamux1_c14cr),
+    Stream7: (stream7, s7cr, s7ndtr, s7par, s7m0ar, s7m1ar, s7fcr, hisr, hifcr, 22, dmamux1_c15cr),
+]);
@@ -3,8 +3,11 @@
 // safety at compile time when using alternate functions
 // if you are reading this, your code helped me a lot understanding how to implement stuff for the stm32h7x3
 
-use crate::rcc::AHB4;
+use crate::rcc::{self, AHB4};
 use core::marker::PhantomData;
+use hal::digital::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+use stm32h7::stm32h7x3::{gpioa::RegisterBlock, EXTI, SYSCFG};
+use stm32h7::stm32h7x3::{GPIOA, GPIOB, GPIOC, GPIOD, GPIOE, GPIOF, GPIOG, GPIOH, GPIOI, GPIOJ, GPIOK};
 
 /// Extension trait to split a GPIO peripheral in independent pins and registers
 pub trait GpioExt {
@@ -15,62 +18,623 @@ pub trait GpioExt {
     fn split(self, ahb4: &mut AHB4) -> Self::Parts;
 }
 
-pub struct AF0;
-pub struct AF1;
-pub struct AF2;
-pub struct AF3;
-pub struct AF4;
-pub struct AF5;
-pub struct AF6;
-pub struct AF7;
-pub struct AF8;
-pub struct AF9;
-pub struct AF10;
-pub struct AF11;
-pub struct AF12;
-pub struct AF13;
-pub struct AF14;
-pub struct AF15;
+/// An alternate function number (type state)
+pub trait AlternateFunction {
+    #[doc(hidden)]
+    const NUMBER: u32;
+}
 
-/// Input mode (type state)
-pub struct Input<MODE> {
-    _mode: PhantomData<MODE>,
+macro_rules! af {
+    ($($AFx:ident: $n:expr,)+) => {
+        $(
+            pub struct $AFx;
+
+            impl AlternateFunction for $AFx {
+                const NUMBER: u32 = $n;
+            }
+        )+
+    };
 }
 
+af!(
+    AF0: 0,
+    AF1: 1,
+    AF2: 2,
+    AF3: 3,
+    AF4: 4,
+    AF5: 5,
+    AF6: 6,
+    AF7: 7,
+    AF8: 8,
+    AF9: 9,
+    AF10: 10,
+    AF11: 11,
+    AF12: 12,
+    AF13: 13,
+    AF14: 14,
+    AF15: 15,
+);
+
+/// Input mode (type state)
+///
+/// The internal pull up or pull down resistor is configured independently of the mode, with
+/// `set_internal_resistor`.
+pub struct Input;
+
 /// Output mode (type state)
 pub struct Output<MODE> {
     _mode: PhantomData<MODE>,
 }
 
-/// Floating input (type state)
-pub struct Floating;
-
-/// Pulled down input (type state)
-pub struct PullDown;
-
-/// Pulled up input (type state)
-pub struct PullUp;
-
-/// Open drain input or output (type state)
+/// Open drain output (type state)
 pub struct OpenDrain;
 
 /// Push pull output (type state)
 pub struct PushPull;
 
+/// Analog mode (type state)
+pub struct Analog;
+
+/// Alternate function mode (type state)
+///
+/// `OTYPE` is the output type the alternate function drives the pin with.
+pub struct Alternate<AF, OTYPE = PushPull> {
+    _af: PhantomData<AF>,
+    _otype: PhantomData<OTYPE>,
+}
+
+/// Internal pull up or pull down resistor of a pin
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pull {
+    /// No resistor
+    None,
+    /// Pull up resistor
+    Up,
+    /// Pull down resistor
+    Down,
+}
+
+impl Pull {
+    fn bits(self) -> u32 {
+        match self {
+            Pull::None => 0b00,
+            Pull::Up => 0b01,
+            Pull::Down => 0b10,
+        }
+    }
+}
+
+/// Output speed of a pin
+#[derive(Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// Low speed
+    Low,
+    /// Medium speed
+    Medium,
+    /// High speed
+    High,
+    /// Very high speed
+    VeryHigh,
+}
+
+impl Speed {
+    fn bits(self) -> u32 {
+        match self {
+            Speed::Low => 0b00,
+            Speed::Medium => 0b01,
+            Speed::High => 0b10,
+            Speed::VeryHigh => 0b11,
+        }
+    }
+}
+
+/// A pin whose configuration is locked until the next reset
+///
+/// Only the level of the pin can still be read or driven.
+pub struct Locked<PIN> {
+    pin: PIN,
+}
+
+impl<PIN: OutputPin> OutputPin for Locked<PIN> {
+    fn set_high(&mut self) {
+        self.pin.set_high()
+    }
+
+    fn set_low(&mut self) {
+        self.pin.set_low()
+    }
+}
+
+impl<PIN: InputPin> InputPin for Locked<PIN> {
+    fn is_high(&self) -> bool {
+        self.pin.is_high()
+    }
+
+    fn is_low(&self) -> bool {
+        self.pin.is_low()
+    }
+}
+
+/// A pin or a tuple of pins of the same port whose configuration can be locked, see `lock`
+pub trait LockPins {
+    /// The locked pins
+    type Locked;
+
+    #[doc(hidden)]
+    fn port_mask(&self) -> (u8, u32);
+
+    #[doc(hidden)]
+    fn into_locked(self) -> Self::Locked;
+}
+
+/// Locks the configuration of several pins of the same port until the next reset
+///
+/// The lock register of a port freezes after its first key sequence, so all pins of a port
+/// that are to be locked have to be locked at once. Panics if `pins` belong to different
+/// ports or if their port has already been locked.
+pub fn lock<PINS: LockPins>(pins: PINS) -> PINS::Locked {
+    let (port, mask) = pins.port_mask();
+    lock_port(port, mask);
+    pins.into_locked()
+}
+
+/// Runs the key sequence locking the pins in `mask` of port number `port`
+fn lock_port(port: u8, mask: u32) {
+    const LCKK: u32 = 1 << 16;
+
+    let gpio = block(port);
+    // NOTE(unsafe) the key sequence must not be interrupted by another write to LCKR
+    let lckr = cortex_m::interrupt::free(|_| unsafe {
+        let lckr = gpio.lckr.read().bits();
+        assert!(lckr & LCKK == 0, "the pins of a port have to be locked at once");
+
+        let bits = (lckr & 0xffff) | mask;
+        gpio.lckr.write(|w| w.bits(LCKK | bits));
+        gpio.lckr.write(|w| w.bits(bits));
+        gpio.lckr.write(|w| w.bits(LCKK | bits));
+        gpio.lckr.read();
+        gpio.lckr.read().bits()
+    });
+    assert!(lckr & LCKK != 0 && lckr & mask == mask);
+}
+
+macro_rules! lock_pins {
+    ($(($($PIN:ident: $pin:ident),+),)+) => {
+        $(
+            impl<$($PIN),+> LockPins for ($($PIN,)+)
+            where
+                $($PIN: LockPins),+
+            {
+                type Locked = ($($PIN::Locked,)+);
+
+                fn port_mask(&self) -> (u8, u32) {
+                    let ($($pin,)+) = self;
+                    let ports = [$($pin.port_mask()),+];
+                    assert!(
+                        ports.iter().all(|&(port, _)| port == ports[0].0),
+                        "only pins of the same port can be locked at once"
+                    );
+
+                    (ports[0].0, ports.iter().fold(0, |mask, &(_, bit)| mask | bit))
+                }
+
+                fn into_locked(self) -> Self::Locked {
+                    let ($($pin,)+) = self;
+                    ($($pin.into_locked(),)+)
+                }
+            }
+        )+
+    };
+}
+
+lock_pins!(
+    (A: a, B: b),
+    (A: a, B: b, C: c),
+    (A: a, B: b, C: c, D: d),
+    (A: a, B: b, C: c, D: d, E: e),
+    (A: a, B: b, C: c, D: d, E: e, F: f),
+    (A: a, B: b, C: c, D: d, E: e, F: f, G: g),
+    (A: a, B: b, C: c, D: d, E: e, F: f, G: g, H: h),
+);
+
+/// Fully erased pin
+///
+/// The port and the pin number are stored at runtime, so pins of different ports can be
+/// collected into an array.
+pub struct ErasedPin<MODE> {
+    port: u8,
+    i: u8,
+    _mode: PhantomData<MODE>,
+}
+
+impl<MODE> ErasedPin<MODE> {
+    /// Returns the index of the port, 0 for GPIOA
+    pub fn get_port(&self) -> u8 {
+        self.port
+    }
+
+    /// Returns the pin number within its port
+    pub fn get_id(&self) -> u8 {
+        self.i
+    }
+
+    fn block(&self) -> &'static RegisterBlock {
+        block(self.port)
+    }
+}
+
+/// Returns the registers of port number `port`
+fn block(port: u8) -> &'static RegisterBlock {
+    // NOTE(unsafe) pins only access their own bits of the port
+    unsafe {
+        &*match port {
+            0 => GPIOA::ptr(),
+            1 => GPIOB::ptr(),
+            2 => GPIOC::ptr(),
+            3 => GPIOD::ptr(),
+            4 => GPIOE::ptr(),
+            5 => GPIOF::ptr(),
+            6 => GPIOG::ptr(),
+            7 => GPIOH::ptr(),
+            8 => GPIOI::ptr(),
+            9 => GPIOJ::ptr(),
+            _ => GPIOK::ptr(),
+        }
+    }
+}
+
+impl<MODE> OutputPin for ErasedPin<Output<MODE>> {
+    fn set_high(&mut self) {
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe { self.block().bsrr.write(|w| w.bits(1 << self.i)) }
+    }
+
+    fn set_low(&mut self) {
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe { self.block().bsrr.write(|w| w.bits(1 << (self.i + 16))) }
+    }
+}
+
+impl<MODE> StatefulOutputPin for ErasedPin<Output<MODE>> {
+    fn is_set_high(&self) -> bool {
+        !self.is_set_low()
+    }
+
+    fn is_set_low(&self) -> bool {
+        self.block().odr.read().bits() & (1 << self.i) == 0
+    }
+}
+
+impl<MODE> ToggleableOutputPin for ErasedPin<Output<MODE>> {
+    fn toggle(&mut self) {
+        if self.is_set_low() {
+            self.set_high()
+        } else {
+            self.set_low()
+        }
+    }
+}
+
+impl InputPin for ErasedPin<Input> {
+    fn is_high(&self) -> bool {
+        !self.is_low()
+    }
+
+    fn is_low(&self) -> bool {
+        self.block().idr.read().bits() & (1 << self.i) == 0
+    }
+}
+
+/// Current direction of a `DynamicPin`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dynamic {
+    /// The pin is an input
+    Input,
+    /// The pin is an output
+    Output,
+}
+
+/// Error of a `DynamicPin` used in the wrong direction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PinModeError {
+    /// The pin is not in the direction the operation requires
+    IncorrectMode,
+}
+
+/// Pin that switches between input and output at runtime, e.g. for 1-Wire
+///
+/// The pull configuration and the output type the pin had when it was made dynamic are
+/// kept when switching.
+pub struct DynamicPin {
+    port: u8,
+    i: u8,
+    mode: Dynamic,
+}
+
+impl DynamicPin {
+    /// Returns the current direction of the pin
+    pub fn mode(&self) -> Dynamic {
+        self.mode
+    }
+
+    /// Switches the pin to input
+    pub fn make_input(&mut self) {
+        self.set_moder(0b00);
+        self.mode = Dynamic::Input;
+    }
+
+    /// Switches the pin to output
+    pub fn make_output(&mut self) {
+        self.set_moder(0b01);
+        self.mode = Dynamic::Output;
+    }
+
+    /// Drives the output high
+    pub fn set_high(&mut self) -> Result<(), PinModeError> {
+        self.output()?;
+        unsafe { block(self.port).bsrr.write(|w| w.bits(1 << self.i)) }
+        Ok(())
+    }
+
+    /// Drives the output low
+    pub fn set_low(&mut self) -> Result<(), PinModeError> {
+        self.output()?;
+        unsafe { block(self.port).bsrr.write(|w| w.bits(1 << (self.i + 16))) }
+        Ok(())
+    }
+
+    /// Returns whether the input is high
+    pub fn is_high(&self) -> Result<bool, PinModeError> {
+        self.is_low().map(|low| !low)
+    }
+
+    /// Returns whether the input is low
+    pub fn is_low(&self) -> Result<bool, PinModeError> {
+        if self.mode != Dynamic::Input {
+            return Err(PinModeError::IncorrectMode);
+        }
+
+        Ok(block(self.port).idr.read().bits() & (1 << self.i) == 0)
+    }
+
+    fn output(&self) -> Result<(), PinModeError> {
+        if self.mode != Dynamic::Output {
+            return Err(PinModeError::IncorrectMode);
+        }
+
+        Ok(())
+    }
+
+    fn set_moder(&mut self, mode: u32) {
+        let offset = 2 * self.i;
+        // NOTE(unsafe) read-modify-write within a critical section
+        cortex_m::interrupt::free(|_| unsafe {
+            block(self.port).moder.modify(|r, w| {
+                w.bits((r.bits() & !(0b11 << offset)) | (mode << offset))
+            })
+        })
+    }
+}
+
+/// Raw access to a pin that bypasses its type state - DO NOT IMPLEMENT THIS TRAIT
+///
+/// Drivers use this to temporarily take over the pins they own, e.g. to bit-bang a bus
+/// recovery. They are responsible for restoring the original configuration afterwards.
+///
+/// The methods changing the pin are unsafe, as the type state of the pin no longer matches
+/// its configuration until it is restored.
+///
+/// # Safety
+///
+/// Implementors must only access the bits of the one pin they represent, and change them
+/// atomically with respect to the other pins of the port.
+pub unsafe trait RawPin {
+    /// Returns the MODER bits of the pin
+    fn mode(&self) -> u32;
+
+    /// Sets the MODER bits of the pin
+    ///
+    /// # Safety
+    ///
+    /// The caller has to restore the mode before the pin is used through its type state
+    /// again.
+    unsafe fn set_mode(&mut self, mode: u32);
+
+    /// Returns whether the output of the pin is open drain
+    fn is_open_drain(&self) -> bool;
+
+    /// Sets the output of the pin to open drain or push pull
+    ///
+    /// # Safety
+    ///
+    /// The caller has to restore the output type before the pin is used through its type
+    /// state again.
+    unsafe fn set_output_type(&mut self, open_drain: bool);
+
+    /// Drives the output of the pin high or low
+    ///
+    /// # Safety
+    ///
+    /// The pin may be in a mode that the type state doesn't allow to drive it in, e.g.
+    /// an input, so the caller has to make sure driving it doesn't cause a bus conflict.
+    unsafe fn drive(&mut self, high: bool);
+
+    /// Returns whether the input of the pin is high
+    fn level(&self) -> bool;
+}
+
+/// Edge that triggers an external interrupt
+#[derive(Clone, Copy, PartialEq)]
+pub enum Edge {
+    /// Low to high transition
+    Rising,
+    /// High to low transition
+    Falling,
+    /// Both transitions
+    Both,
+}
+
+/// Event that clears a pending D3 domain wakeup, allowing D3 to enter Stop mode again
+#[derive(Clone, Copy, PartialEq)]
+pub enum D3PendClear {
+    /// DMAMUX2 channel 6 event
+    Dmamux2Channel6,
+    /// DMAMUX2 channel 7 event
+    Dmamux2Channel7,
+    /// LPTIM4 output
+    Lptim4,
+    /// LPTIM5 output
+    Lptim5,
+}
+
+/// External interrupt configuration of a pin
+///
+/// Every EXTI line is shared by the pins with the same number on all ports, only the
+/// pin last made an interrupt source is connected to it.
+pub trait ExtiPin {
+    /// Connects the EXTI line of this pin to it
+    fn make_interrupt_source(&mut self, syscfg: &mut SYSCFG);
+
+    /// Selects the edges that trigger the EXTI line
+    fn trigger_on_edge(&mut self, exti: &mut EXTI, edge: Edge);
+
+    /// Unmasks the interrupt of the EXTI line for the CPU
+    fn enable_interrupt(&mut self, exti: &mut EXTI);
+
+    /// Masks the interrupt of the EXTI line for the CPU
+    fn disable_interrupt(&mut self, exti: &mut EXTI);
+
+    /// Unmasks the event of the EXTI line for the CPU, which wakes it from WFE
+    fn enable_event(&mut self, exti: &mut EXTI);
+
+    /// Masks the event of the EXTI line for the CPU
+    fn disable_event(&mut self, exti: &mut EXTI);
+
+    /// Lets the EXTI line wake up the D3 domain, until `clear` occurs
+    fn enable_d3_wakeup(&mut self, exti: &mut EXTI, clear: D3PendClear);
+
+    /// Stops the EXTI line from waking up the D3 domain
+    fn disable_d3_wakeup(&mut self, exti: &mut EXTI);
+
+    /// Clears the pending interrupt of the EXTI line
+    fn clear_interrupt_pending_bit(&mut self);
+
+    /// Returns whether an interrupt of the EXTI line is pending
+    fn check_interrupt(&self) -> bool;
+}
+
+/// Connects EXTI line `i` to port number `port`
+fn exti_source(syscfg: &mut SYSCFG, port: u32, i: u8) {
+    rcc::enable_syscfg();
+
+    let offset = 4 * u32::from(i % 4);
+    let value = |bits: u32| (bits & !(0b1111 << offset)) | (port << offset);
+    unsafe {
+        match i / 4 {
+            0 => syscfg.exticr1.modify(|r, w| w.bits(value(r.bits()))),
+            1 => syscfg.exticr2.modify(|r, w| w.bits(value(r.bits()))),
+            2 => syscfg.exticr3.modify(|r, w| w.bits(value(r.bits()))),
+            _ => syscfg.exticr4.modify(|r, w| w.bits(value(r.bits()))),
+        }
+    }
+}
+
+/// Selects the trigger edges of EXTI line `i`
+fn exti_trigger(exti: &mut EXTI, i: u8, edge: Edge) {
+    let rising = edge != Edge::Falling;
+    let falling = edge != Edge::Rising;
+    unsafe {
+        exti.rtsr1.modify(|r, w| w.bits((r.bits() & !(1 << i)) | (u32::from(rising) << i)));
+        exti.ftsr1.modify(|r, w| w.bits((r.bits() & !(1 << i)) | (u32::from(falling) << i)));
+    }
+}
+
+/// Sets the D3 pend clear source of EXTI line `i`, or masks its D3 wakeup without one
+fn exti_d3_wakeup(exti: &mut EXTI, i: u8, clear: Option<D3PendClear>) {
+    match clear {
+        Some(clear) => {
+            let offset = 2 * u32::from(i);
+            let bits = match clear {
+                D3PendClear::Dmamux2Channel6 => 0b00,
+                D3PendClear::Dmamux2Channel7 => 0b01,
+                D3PendClear::Lptim4 => 0b10,
+                D3PendClear::Lptim5 => 0b11,
+            };
+            unsafe {
+                exti.d3pcr1l.modify(|r, w| w.bits((r.bits() & !(0b11 << offset)) | (bits << offset)));
+                exti.d3pmr1.modify(|r, w| w.bits(r.bits() | (1 << i)));
+            }
+        }
+        None => unsafe { exti.d3pmr1.modify(|r, w| w.bits(r.bits() & !(1 << i))) },
+    }
+}
+
+macro_rules! exti_pin {
+    () => {
+        fn make_interrupt_source(&mut self, syscfg: &mut SYSCFG) {
+            exti_source(syscfg, PORT, self.index());
+        }
+
+        fn trigger_on_edge(&mut self, exti: &mut EXTI, edge: Edge) {
+            exti_trigger(exti, self.index(), edge);
+        }
+
+        fn enable_interrupt(&mut self, exti: &mut EXTI) {
+            let i = self.index();
+            unsafe { exti.cpuimr1.modify(|r, w| w.bits(r.bits() | (1 << i))) }
+        }
+
+        fn disable_interrupt(&mut self, exti: &mut EXTI) {
+            let i = self.index();
+            unsafe { exti.cpuimr1.modify(|r, w| w.bits(r.bits() & !(1 << i))) }
+        }
+
+        fn enable_event(&mut self, exti: &mut EXTI) {
+            let i = self.index();
+            unsafe { exti.cpuemr1.modify(|r, w| w.bits(r.bits() | (1 << i))) }
+        }
+
+        fn disable_event(&mut self, exti: &mut EXTI) {
+            let i = self.index();
+            unsafe { exti.cpuemr1.modify(|r, w| w.bits(r.bits() & !(1 << i))) }
+        }
+
+        fn enable_d3_wakeup(&mut self, exti: &mut EXTI, clear: D3PendClear) {
+            exti_d3_wakeup(exti, self.index(), Some(clear));
+        }
+
+        fn disable_d3_wakeup(&mut self, exti: &mut EXTI) {
+            exti_d3_wakeup(exti, self.index(), None);
+        }
+
+        fn clear_interrupt_pending_bit(&mut self) {
+            // NOTE(unsafe) write 1 to clear, the other lines are not affected
+            unsafe { (*EXTI::ptr()).cpupr1.write(|w| w.bits(1 << self.index())) }
+        }
+
+        fn check_interrupt(&self) -> bool {
+            // NOTE(unsafe) atomic read with no side effects
+            unsafe { (*EXTI::ptr()).cpupr1.read().bits() & (1 << self.index()) != 0 }
+        }
+    };
+}
+
 macro_rules! gpio {
-    ($GPIOX:ident, $gpiox:ident, $iopxenr:ident, $PXx:ident, [
-        $($PXi:ident: ($pxi:ident, $i:expr, $MODE:ty),)+
+    ($GPIOX:ident, $gpiox:ident, $iopxenr:ident, $PXx:ident, $port:expr, [
+        $($(#[$attr:meta])* $PXi:ident: ($pxi:ident, $i:expr, $MODE:ty),)+
     ]) => {
         /// GPIO Block
         pub mod $gpiox {
             use core::marker::PhantomData;
-            use hal::digital::{InputPin, OutputPin};
             use stm32h7::stm32h7x3::{$GPIOX};
             use super::*;
 
+            /// Index of the port in the EXTI source selection
+            const PORT: u32 = $port;
+
             /// GPIO parts
             pub struct Parts {
-                $(pub $pxi: $PXi<$MODE, AF0>,)+
+                $($(#[$attr])* pub $pxi: $PXi<$MODE>,)+
             }
 
             impl GpioExt for $GPIOX {
@@ -80,7 +644,7 @@ macro_rules! gpio {
                     ahb4.enr().modify(|_, w| w.$iopxenr().set_bit());
 
                     Parts {
-                        $($pxi: $PXi {_io_mode: PhantomData, _af_mode: PhantomData,},)+
+                        $($(#[$attr])* $pxi: $PXi {_mode: PhantomData},)+
                     }
                 }
             }
@@ -101,7 +665,27 @@ macro_rules! gpio {
                 }
             }
 
-            impl<MODE> InputPin for $PXx<Input<MODE>> {
+            impl<MODE> StatefulOutputPin for $PXx<Output<MODE>> {
+                fn is_set_high(&self) -> bool {
+                    !self.is_set_low()
+                }
+
+                fn is_set_low(&self) -> bool {
+                    unsafe { (*$GPIOX::ptr()).odr.read().bits() & (1 << self.i) == 0 }
+                }
+            }
+
+            impl<MODE> ToggleableOutputPin for $PXx<Output<MODE>> {
+                fn toggle(&mut self) {
+                    if self.is_set_low() {
+                        self.set_high()
+                    } else {
+                        self.set_low()
+                    }
+                }
+            }
+
+            impl InputPin for $PXx<Input> {
                 fn is_high(&self) -> bool {
                     !self.is_low()
                 }
@@ -111,279 +695,325 @@ macro_rules! gpio {
                 }
             }
 
-            fn _set_alternate_mode (index:usize, mode: u32)
-            {
+            /// Configures MODER, OTYPER, PUPDR and AFR of a pin at once
+            ///
+            /// MODER is written last, so the pin never operates with a half applied
+            /// configuration. The pull resistor is kept as it is if `pull` is `None`.
+            fn _configure(index: usize, mode: u32, open_drain: bool, pull: Option<Pull>, af: u32) {
                 let offset = 2 * index;
-                let offset2 = 4 * index;
-                unsafe {
-                    &(*$GPIOX::ptr()).moder.modify(|r, w| {
-                        w.bits((r.bits() & !(0b11 << offset)) | (0b10 << offset))
-                    });
-                    if offset2 < 32 {
-                        &(*$GPIOX::ptr()).afrl.modify(|r, w| {
-                            w.bits((r.bits() & !(0b1111 << offset2)) | (mode << offset2))
+                let offset2 = 4 * (index % 8);
+                cortex_m::interrupt::free(|_| unsafe {
+                    let gpio = &*$GPIOX::ptr();
+                    if index < 8 {
+                        gpio.afrl.modify(|r, w| {
+                            w.bits((r.bits() & !(0b1111 << offset2)) | (af << offset2))
                         });
                     } else {
-                        let offset2 = offset2 - 32;
-                        &(*$GPIOX::ptr()).afrh.modify(|r, w| {
-                            w.bits((r.bits() & !(0b1111 << offset2)) | (mode << offset2))
+                        gpio.afrh.modify(|r, w| {
+                            w.bits((r.bits() & !(0b1111 << offset2)) | (af << offset2))
                         });
                     }
-                }
+                    gpio.otyper.modify(|r, w| {
+                        w.bits((r.bits() & !(1 << index)) | ((open_drain as u32) << index))
+                    });
+                    if let Some(pull) = pull {
+                        gpio.pupdr.modify(|r, w| {
+                            w.bits((r.bits() & !(0b11 << offset)) | (pull.bits() << offset))
+                        });
+                    }
+                    gpio.moder.modify(|r, w| {
+                        w.bits((r.bits() & !(0b11 << offset)) | (mode << offset))
+                    });
+                });
+            }
+
+            fn _set_pull(index: usize, pull: Pull) {
+                let offset = 2 * index;
+                // NOTE(unsafe) read-modify-write within a critical section
+                cortex_m::interrupt::free(|_| unsafe {
+                    (*$GPIOX::ptr()).pupdr.modify(|r, w| {
+                        w.bits((r.bits() & !(0b11 << offset)) | (pull.bits() << offset))
+                    })
+                });
+            }
+
+            fn _set_speed(index: usize, speed: Speed) {
+                let offset = 2 * index;
+                // NOTE(unsafe) read-modify-write within a critical section
+                cortex_m::interrupt::free(|_| unsafe {
+                    (*$GPIOX::ptr()).ospeedr.modify(|r, w| {
+                        w.bits((r.bits() & !(0b11 << offset)) | (speed.bits() << offset))
+                    })
+                });
             }
 
             $(
+                $(#[$attr])*
                 /// Pin
-                pub struct $PXi<IoMode, AlternateMode> {
-                    _io_mode: PhantomData<IoMode>,
-                    _af_mode: PhantomData<AlternateMode>,
+                pub struct $PXi<MODE> {
+                    _mode: PhantomData<MODE>,
                 }
 
-                impl<IoMode, AlternateMode> $PXi<IoMode, AlternateMode> {
+                $(#[$attr])*
+                impl<MODE> $PXi<MODE> {
                     /// Configures the pin to operate in AF0 mode
                     pub fn into_alternate_af0(
                         self,
-                    ) -> $PXi<IoMode, AF0> {
-                        _set_alternate_mode($i, 0);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF0>> {
+                        _configure($i, 0b10, false, None, 0);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF1 mode
                     pub fn into_alternate_af1(
                         self,
-                    ) -> $PXi<IoMode, AF1> {
-                        _set_alternate_mode($i, 1);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF1>> {
+                        _configure($i, 0b10, false, None, 1);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF2 mode
                     pub fn into_alternate_af2(
                         self,
-                    ) -> $PXi<IoMode, AF2> {
-                        _set_alternate_mode($i, 2);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF2>> {
+                        _configure($i, 0b10, false, None, 2);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF3 mode
                     pub fn into_alternate_af3(
                         self,
-                    ) -> $PXi<IoMode, AF3> {
-                        _set_alternate_mode($i, 3);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF3>> {
+                        _configure($i, 0b10, false, None, 3);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF4 mode
                     pub fn into_alternate_af4(
                         self,
-                    ) -> $PXi<IoMode, AF4> {
-                        _set_alternate_mode($i, 4);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF4>> {
+                        _configure($i, 0b10, false, None, 4);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF5 mode
                     pub fn into_alternate_af5(
                         self,
-                    ) -> $PXi<IoMode, AF5> {
-                        _set_alternate_mode($i, 5);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF5>> {
+                        _configure($i, 0b10, false, None, 5);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF6 mode
                     pub fn into_alternate_af6(
                         self,
-                    ) -> $PXi<IoMode, AF6> {
-                        _set_alternate_mode($i, 6);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF6>> {
+                        _configure($i, 0b10, false, None, 6);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF7 mode
                     pub fn into_alternate_af7(
                         self,
-                    ) -> $PXi<IoMode, AF7> {
-                        _set_alternate_mode($i, 7);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF7>> {
+                        _configure($i, 0b10, false, None, 7);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF8 mode
                     pub fn into_alternate_af8(
                         self,
-                    ) -> $PXi<IoMode, AF8> {
-                        _set_alternate_mode($i, 8);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF8>> {
+                        _configure($i, 0b10, false, None, 8);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF9 mode
                     pub fn into_alternate_af9(
                         self,
-                    ) -> $PXi<IoMode, AF9> {
-                        _set_alternate_mode($i, 9);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF9>> {
+                        _configure($i, 0b10, false, None, 9);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF10 mode
                     pub fn into_alternate_af10(
                         self,
-                    ) -> $PXi<IoMode, AF10> {
-                        _set_alternate_mode($i, 10);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF10>> {
+                        _configure($i, 0b10, false, None, 10);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF11 mode
                     pub fn into_alternate_af11(
                         self,
-                    ) -> $PXi<IoMode, AF11> {
-                        _set_alternate_mode($i, 11);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF11>> {
+                        _configure($i, 0b10, false, None, 11);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF12 mode
                     pub fn into_alternate_af12(
                         self,
-                    ) -> $PXi<IoMode, AF12> {
-                        _set_alternate_mode($i, 12);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF12>> {
+                        _configure($i, 0b10, false, None, 12);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF13 mode
                     pub fn into_alternate_af13(
                         self,
-                    ) -> $PXi<IoMode, AF13> {
-                        _set_alternate_mode($i, 13);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF13>> {
+                        _configure($i, 0b10, false, None, 13);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF14 mode
                     pub fn into_alternate_af14(
                         self,
-                    ) -> $PXi<IoMode, AF14> {
-                        _set_alternate_mode($i, 14);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF14>> {
+                        _configure($i, 0b10, false, None, 14);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate in AF15 mode
                     pub fn into_alternate_af15(
                         self,
-                    ) -> $PXi<IoMode, AF15> {
-                        _set_alternate_mode($i, 15);
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Alternate<AF15>> {
+                        _configure($i, 0b10, false, None, 15);
+                        $PXi {_mode: PhantomData}
+                    }
+
+                    /// Configures the pin to operate in alternate function `AF` with an open
+                    /// drain output
+                    ///
+                    /// The pin is never driven push pull in between, which would disturb a
+                    /// bus like I2C.
+                    pub fn into_alternate_open_drain<AF: AlternateFunction>(
+                        self,
+                    ) -> $PXi<Alternate<AF, OpenDrain>> {
+                        _configure($i, 0b10, true, None, AF::NUMBER);
+                        $PXi {_mode: PhantomData}
                     }
 
-                    /// Configures the pin to operate as a floating input pin
+                    /// Configures the pin to operate as an input pin without pull resistor
                     pub fn into_floating_input(
                         self,
-                    ) -> $PXi<Input<Floating>, AF0> {
-                        let offset = 2 * $i;
-                        unsafe {
-                            &(*$GPIOX::ptr()).moder.modify(|r, w| {
-                                w.bits((r.bits() & !(0b11 << offset)) | (0b00 << offset))
-                         });
-                            &(*$GPIOX::ptr()).pupdr.modify(|r, w| {
-                                w.bits((r.bits() & !(0b11 << offset)) | (0b00 << offset))
-                         })};
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Input> {
+                        _configure($i, 0b00, false, Some(Pull::None), 0);
+                        $PXi {_mode: PhantomData}
                     }
 
-                    /// Configures the pin to operate as a pulled down input pin
+                    /// Configures the pin to operate as an input pin with the pull down resistor
                     pub fn into_pull_down_input(
                         self,
-                        ) -> $PXi<Input<PullDown>, AF0> {
-                        let offset = 2 * $i;
-                        unsafe {
-                            &(*$GPIOX::ptr()).moder.modify(|r, w| {
-                                w.bits((r.bits() & !(0b11 << offset)) | (0b00 << offset))
-                         });
-                            &(*$GPIOX::ptr()).pupdr.modify(|r, w| {
-                                w.bits((r.bits() & !(0b11 << offset)) | (0b10 << offset))
-                         })};
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Input> {
+                        _configure($i, 0b00, false, Some(Pull::Down), 0);
+                        $PXi {_mode: PhantomData}
                     }
 
-                    /// Configures the pin to operate as a pulled up input pin
+                    /// Configures the pin to operate as an input pin with the pull up resistor
                     pub fn into_pull_up_input(
                         self,
-                    ) -> $PXi<Input<PullUp>, AF0> {
-                        let offset = 2 * $i;
-                        unsafe {
-                            &(*$GPIOX::ptr()).moder.modify(|r, w| {
-                                w.bits((r.bits() & !(0b11 << offset)) | (0b00 << offset))
-                         });
-                            &(*$GPIOX::ptr()).pupdr.modify(|r, w| {
-                                w.bits((r.bits() & !(0b11 << offset)) | (0b01 << offset))
-                         })};
-
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Input> {
+                        _configure($i, 0b00, false, Some(Pull::Up), 0);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate as an open drain output pin
                     pub fn into_open_drain_output(
                         self,
-                    ) -> $PXi<Output<OpenDrain>, AF0> {
-                        let offset = 2 * $i;
-                        unsafe {
-                            &(*$GPIOX::ptr()).moder.modify(|r, w| {
-                                w.bits((r.bits() & !(0b11 << offset)) | (0b01 << offset))
-                         });
-                            &(*$GPIOX::ptr()).pupdr.modify(|r, w| {
-                                w.bits((r.bits() & !(0b11 << offset)) | (0b00 << offset))
-                         });
-                            &(*$GPIOX::ptr()).otyper.modify(|r, w| {
-                                w.bits(r.bits() | (0b1 << $i))
-                         })};
-
-
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    ) -> $PXi<Output<OpenDrain>> {
+                        _configure($i, 0b01, true, None, 0);
+                        $PXi {_mode: PhantomData}
                     }
 
                     /// Configures the pin to operate as an push pull output pin
                     pub fn into_push_pull_output(
                         self,
-                    ) -> $PXi<Output<PushPull>, AF0> {
-                        let offset = 2 * $i;
+                    ) -> $PXi<Output<PushPull>> {
+                        _configure($i, 0b01, false, None, 0);
+                        $PXi {_mode: PhantomData}
+                    }
 
-                        unsafe {
-                            &(*$GPIOX::ptr()).moder.modify(|r, w| {
-                                w.bits((r.bits() & !(0b11 << offset)) | (0b01 << offset))
-                         });
-                            &(*$GPIOX::ptr()).pupdr.modify(|r, w| {
-                                w.bits((r.bits() & !(0b11 << offset)) | (0b00 << offset))
-                            });
-                            &(*$GPIOX::ptr()).otyper.modify(|r, w| {
-                                w.bits(r.bits() & !(0b1 << $i))
-                         })};
+                    /// Configures the pin to operate as an analog pin, e.g. for the ADC or DAC
+                    ///
+                    /// The pull resistor is switched off, as analog mode requires.
+                    pub fn into_analog(
+                        self,
+                    ) -> $PXi<Analog> {
+                        _configure($i, 0b11, false, Some(Pull::None), 0);
+                        $PXi {_mode: PhantomData}
+                    }
 
+                    /// Configures the internal pull up or pull down resistor
+                    ///
+                    /// The resistor is independent of the mode and kept when the pin is
+                    /// converted to an output or alternate function.
+                    pub fn set_internal_resistor(&mut self, pull: Pull) {
+                        _set_pull($i, pull);
+                    }
 
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    /// Locks the configuration of the pin until the next reset
+                    ///
+                    /// Only one key sequence is accepted per port, use `gpio::lock` to lock
+                    /// several pins of this port.
+                    pub fn lock(self) -> Locked<Self> {
+                        lock(self)
                     }
                 }
 
-                impl<AlternateMode> $PXi<Output<OpenDrain>, AlternateMode> {
-                    /// Enables / disables the internal pull up
-                    pub fn internal_pull_up(&mut self, on: bool) -> $PXi<Output<PullUp>, AlternateMode>{
-                        let offset = 2 * $i;
-                        let value = if on { 0b01 } else { 0b00 };
-                        unsafe {
-                            &(*$GPIOX::ptr()).pupdr.modify(|r, w| {
-                                w.bits((r.bits() & !(0b11 << offset)) | (value << offset))
-                        })};
+                $(#[$attr])*
+                impl<MODE> LockPins for $PXi<MODE> {
+                    type Locked = Locked<Self>;
+
+                    fn port_mask(&self) -> (u8, u32) {
+                        ($port, 1 << $i)
+                    }
 
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                    fn into_locked(self) -> Locked<Self> {
+                        Locked { pin: self }
                     }
                 }
 
-                impl<IoMode, AlternateMode> $PXi<IoMode, AlternateMode> {
-                    /// Turns pin alternate configuration pin into open drain
-                    pub fn set_open_drain(self) -> $PXi<Output<OpenDrain>, AlternateMode> {
-                        let offset = $i;
-                        unsafe {
-                            &(*$GPIOX::ptr()).otyper.modify(|r, w| {
-                                w.bits(r.bits() & (1 << offset))
-                         })};
+                $(#[$attr])*
+                impl<OutputMode> $PXi<Output<OutputMode>> {
+                    /// Sets the output speed of the pin
+                    pub fn set_speed(self, speed: Speed) -> Self {
+                        _set_speed($i, speed);
+                        self
+                    }
+                }
 
-                        $PXi {_io_mode: PhantomData, _af_mode: PhantomData,}
+                $(#[$attr])*
+                impl<AF, OutputMode> $PXi<Alternate<AF, OutputMode>> {
+                    /// Sets the output speed of the pin
+                    pub fn set_speed(self, speed: Speed) -> Self {
+                        _set_speed($i, speed);
+                        self
                     }
                 }
 
-                impl<OutputMode, AlternateMode> $PXi<Output<OutputMode>, AlternateMode> {
+                $(#[$attr])*
+                impl<AF> $PXi<Alternate<AF, PushPull>> {
+                    /// Turns the alternate function output into open drain
+                    ///
+                    /// The pin has been driven push pull until now, use
+                    /// `into_alternate_open_drain` to avoid that.
+                    pub fn set_open_drain(self) -> $PXi<Alternate<AF, OpenDrain>> {
+                        // NOTE(unsafe) read-modify-write within a critical section
+                        cortex_m::interrupt::free(|_| unsafe {
+                            (*$GPIOX::ptr()).otyper.modify(|r, w| {
+                                w.bits(r.bits() | (1 << $i))
+                            })
+                        });
+
+                        $PXi {_mode: PhantomData}
+                    }
+                }
+
+                $(#[$attr])*
+                impl<OutputMode> $PXi<Output<OutputMode>> {
                     /// Erases the pin number from the type
                     ///
                     /// This is useful when you want to collect the pins into an array where you
@@ -391,12 +1021,13 @@ macro_rules! gpio {
                     pub fn downgrade(self) -> $PXx<Output<OutputMode>> {
                         $PXx {
                             i: $i,
-                            _mode: self._io_mode,
+                            _mode: self._mode,
                         }
                     }
                 }
 
-                impl<OutputMode> OutputPin for $PXi<Output<OutputMode>, AF0> {
+                $(#[$attr])*
+                impl<OutputMode> OutputPin for $PXi<Output<OutputMode>> {
                     fn set_high(&mut self) {
                         unsafe { (*$GPIOX::ptr()).bsrr.write(|w| w.bits(1 << $i)) }
                     }
@@ -406,20 +1037,44 @@ macro_rules! gpio {
                     }
                 }
 
-                impl<InputMode, AlternateMode> $PXi<Input<InputMode>, AlternateMode> {
+                $(#[$attr])*
+                impl<OutputMode> StatefulOutputPin for $PXi<Output<OutputMode>> {
+                    fn is_set_high(&self) -> bool {
+                        !self.is_set_low()
+                    }
+
+                    fn is_set_low(&self) -> bool {
+                        unsafe { (*$GPIOX::ptr()).odr.read().bits() & (1 << $i) == 0 }
+                    }
+                }
+
+                $(#[$attr])*
+                impl<OutputMode> ToggleableOutputPin for $PXi<Output<OutputMode>> {
+                    fn toggle(&mut self) {
+                        if self.is_set_low() {
+                            self.set_high()
+                        } else {
+                            self.set_low()
+                        }
+                    }
+                }
+
+                $(#[$attr])*
+                impl $PXi<Input> {
                     /// Erases the pin number from the type
                     ///
                     /// This is useful when you want to collect the pins into an array where you
                     /// need all the elements to have the same type
-                    pub fn downgrade(self) -> $PXx<Input<InputMode>> {
+                    pub fn downgrade(self) -> $PXx<Input> {
                         $PXx {
                             i: $i,
-                            _mode: self._io_mode,
+                            _mode: self._mode,
                         }
                     }
                 }
 
-                impl<InputMode> InputPin for $PXi<Input<InputMode>, AF0> {
+                $(#[$attr])*
+                impl InputPin for $PXi<Input> {
                     fn is_high(&self) -> bool {
                         !self.is_low()
                     }
@@ -428,6 +1083,84 @@ macro_rules! gpio {
                         unsafe { (*$GPIOX::ptr()).idr.read().bits() & (1 << $i) == 0 }
                     }
                 }
+
+                $(#[$attr])*
+                impl<MODE> $PXi<MODE> {
+                    /// Erases the port and the pin number from the type
+                    ///
+                    /// This is useful when you want to collect pins of different ports into
+                    /// an array where you need all the elements to have the same type
+                    pub fn erase(self) -> ErasedPin<MODE> {
+                        ErasedPin {
+                            port: PORT as u8,
+                            i: $i,
+                            _mode: PhantomData,
+                        }
+                    }
+
+                    /// Lets the direction of the pin be switched at runtime
+                    ///
+                    /// Pins that are not an output start as input.
+                    pub fn into_dynamic(self) -> DynamicPin {
+                        let mut pin = DynamicPin {
+                            port: PORT as u8,
+                            i: $i,
+                            mode: Dynamic::Input,
+                        };
+                        if RawPin::mode(&self) == 0b01 {
+                            pin.mode = Dynamic::Output;
+                        } else {
+                            pin.make_input();
+                        }
+                        pin
+                    }
+
+                    fn index(&self) -> u8 {
+                        $i
+                    }
+                }
+
+                $(#[$attr])*
+                impl<MODE> ExtiPin for $PXi<MODE> {
+                    exti_pin!();
+                }
+
+                $(#[$attr])*
+                unsafe impl<MODE> RawPin for $PXi<MODE> {
+                    fn mode(&self) -> u32 {
+                        unsafe { ((*$GPIOX::ptr()).moder.read().bits() >> (2 * $i)) & 0b11 }
+                    }
+
+                    unsafe fn set_mode(&mut self, mode: u32) {
+                        let offset = 2 * $i;
+                        cortex_m::interrupt::free(|_| {
+                            (*$GPIOX::ptr()).moder.modify(|r, w| {
+                                w.bits((r.bits() & !(0b11 << offset)) | ((mode & 0b11) << offset))
+                            })
+                        })
+                    }
+
+                    fn is_open_drain(&self) -> bool {
+                        unsafe { (*$GPIOX::ptr()).otyper.read().bits() & (1 << $i) != 0 }
+                    }
+
+                    unsafe fn set_output_type(&mut self, open_drain: bool) {
+                        cortex_m::interrupt::free(|_| {
+                            (*$GPIOX::ptr()).otyper.modify(|r, w| {
+                                w.bits((r.bits() & !(1 << $i)) | ((open_drain as u32) << $i))
+                            })
+                        })
+                    }
+
+                    unsafe fn drive(&mut self, high: bool) {
+                        let bit = if high { $i } else { $i + 16 };
+                        (*$GPIOX::ptr()).bsrr.write(|w| w.bits(1 << bit))
+                    }
+
+                    fn level(&self) -> bool {
+                        unsafe { (*$GPIOX::ptr()).idr.read().bits() & (1 << $i) != 0 }
+                    }
+                }
             )+
 
                 impl<TYPE> $PXx<TYPE> {
@@ -435,208 +1168,329 @@ macro_rules! gpio {
                     {
                         self.i
                     }
+
+                    /// Erases the port from the type
+                    pub fn erase(self) -> ErasedPin<TYPE> {
+                        ErasedPin {
+                            port: PORT as u8,
+                            i: self.i,
+                            _mode: PhantomData,
+                        }
+                    }
+
+                    fn index(&self) -> u8 {
+                        self.i
+                    }
+                }
+
+                impl<TYPE> ExtiPin for $PXx<TYPE> {
+                    exti_pin!();
                 }
+
+            /// Pins of the port that are read or written at once
+            pub struct Port<MODE> {
+                mask: u16,
+                _mode: PhantomData<MODE>,
+            }
+
+            impl<MODE> Port<MODE> {
+                /// Takes ownership of `pins` to operate on them together
+                pub fn new<I>(pins: I) -> Self
+                where
+                    I: IntoIterator<Item = $PXx<MODE>>,
+                {
+                    let mask = pins.into_iter().fold(0, |mask, pin| mask | (1 << pin.i));
+
+                    Port { mask, _mode: PhantomData }
+                }
+
+                /// Returns the mask of the pins owned by the port
+                pub fn mask(&self) -> u16 {
+                    self.mask
+                }
+
+                /// Returns the input levels of the owned pins, the other bits are zero
+                pub fn read(&self) -> u16 {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let idr = unsafe { (*$GPIOX::ptr()).idr.read().bits() };
+                    idr as u16 & self.mask
+                }
+
+                /// Releases the pins
+                pub fn free(self) -> PortPins<MODE> {
+                    PortPins { mask: self.mask, _mode: PhantomData }
+                }
+            }
+
+            impl<MODE> Port<Output<MODE>> {
+                /// Drives the owned pins of `mask` to the levels of the bits of `value`
+                ///
+                /// All of them change at once, as a single write to BSRR.
+                pub fn write_masked(&mut self, mask: u16, value: u16) {
+                    let mask = u32::from(mask & self.mask);
+                    let value = u32::from(value);
+                    let bits = (value & mask) | ((!value & mask) << 16);
+                    // NOTE(unsafe) atomic write to a stateless register, only touching our pins
+                    unsafe { (*$GPIOX::ptr()).bsrr.write(|w| w.bits(bits)) }
+                }
+
+                /// Drives all owned pins to the levels of the bits of `value`
+                pub fn write(&mut self, value: u16) {
+                    let mask = self.mask;
+                    self.write_masked(mask, value);
+                }
+
+                /// Returns the output levels the owned pins are driven to
+                pub fn read_output(&self) -> u16 {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let odr = unsafe { (*$GPIOX::ptr()).odr.read().bits() };
+                    odr as u16 & self.mask
+                }
+            }
+
+            /// Pins released by a `Port`
+            pub struct PortPins<MODE> {
+                mask: u16,
+                _mode: PhantomData<MODE>,
+            }
+
+            impl<MODE> Iterator for PortPins<MODE> {
+                type Item = $PXx<MODE>;
+
+                fn next(&mut self) -> Option<$PXx<MODE>> {
+                    if self.mask == 0 {
+                        return None;
+                    }
+
+                    let i = self.mask.trailing_zeros() as u8;
+                    self.mask &= !(1 << i);
+                    Some($PXx { i, _mode: PhantomData })
+                }
+            }
         }
     }
 }
 
-gpio!(GPIOA, gpioa, gpioaen, PA, [
-    PA0: (pa0, 0, Input<Floating>),
-    PA1: (pa1, 1, Input<Floating>),
-    PA2: (pa2, 2, Input<Floating>),
-    PA3: (pa3, 3, Input<Floating>),
-    PA4: (pa4, 4, Input<Floating>),
-    PA5: (pa5, 5, Input<Floating>),
-    PA6: (pa6, 6, Input<Floating>),
-    PA7: (pa7, 7, Input<Floating>),
-    PA8: (pa8, 8, Input<Floating>),
-    PA9: (pa9, 9, Input<Floating>),
-    PA10: (pa10, 10, Input<Floating>),
-    PA11: (pa11, 11, Input<Floating>),
-    PA12: (pa12, 12, Input<Floating>),
-    PA13: (pa13, 13, Input<Floating>),
-    PA14: (pa14, 14, Input<Floating>),
-    PA15: (pa15, 15, Input<Floating>),
+gpio!(GPIOA, gpioa, gpioaen, PA, 0, [
+    PA0: (pa0, 0, Input),
+    PA1: (pa1, 1, Input),
+    PA2: (pa2, 2, Input),
+    PA3: (pa3, 3, Input),
+    PA4: (pa4, 4, Input),
+    PA5: (pa5, 5, Input),
+    PA6: (pa6, 6, Input),
+    PA7: (pa7, 7, Input),
+    PA8: (pa8, 8, Input),
+    PA9: (pa9, 9, Input),
+    PA10: (pa10, 10, Input),
+    PA11: (pa11, 11, Input),
+    PA12: (pa12, 12, Input),
+    PA13: (pa13, 13, Input),
+    PA14: (pa14, 14, Input),
+    PA15: (pa15, 15, Input),
 ]);
 
-gpio!(GPIOB, gpiob, gpioben, PB, [
-    PB0: (pb0, 0, Input<Floating>),
-    PB1: (pb1, 1, Input<Floating>),
-    PB2: (pb2, 2, Input<Floating>),
-    PB3: (pb3, 3, Input<Floating>),
-    PB4: (pb4, 4, Input<Floating>),
-    PB5: (pb5, 5, Input<Floating>),
-    PB6: (pb6, 6, Input<Floating>),
-    PB7: (pb7, 7, Input<Floating>),
-    PB8: (pb8, 8, Input<Floating>),
-    PB9: (pb9, 9, Input<Floating>),
-    PB10: (pb10, 10, Input<Floating>),
-    PB11: (pb11, 11, Input<Floating>),
-    PB12: (pb12, 12, Input<Floating>),
-    PB13: (pb13, 13, Input<Floating>),
-    PB14: (pb14, 14, Input<Floating>),
-    PB15: (pb15, 15, Input<Floating>),
+gpio!(GPIOB, gpiob, gpioben, PB, 1, [
+    PB0: (pb0, 0, Input),
+    PB1: (pb1, 1, Input),
+    PB2: (pb2, 2, Input),
+    PB3: (pb3, 3, Input),
+    PB4: (pb4, 4, Input),
+    PB5: (pb5, 5, Input),
+    PB6: (pb6, 6, Input),
+    PB7: (pb7, 7, Input),
+    PB8: (pb8, 8, Input),
+    PB9: (pb9, 9, Input),
+    PB10: (pb10, 10, Input),
+    PB11: (pb11, 11, Input),
+    PB12: (pb12, 12, Input),
+    PB13: (pb13, 13, Input),
+    PB14: (pb14, 14, Input),
+    PB15: (pb15, 15, Input),
 ]);
 
-gpio!(GPIOC, gpioc, gpiocen, PC, [
-    PC0: (pc0, 0, Input<Floating>),
-    PC1: (pc1, 1, Input<Floating>),
-    PC2: (pc2, 2, Input<Floating>),
-    PC3: (pc3, 3, Input<Floating>),
-    PC4: (pc4, 4, Input<Floating>),
-    PC5: (pc5, 5, Input<Floating>),
-    PC6: (pc6, 6, Input<Floating>),
-    PC7: (pc7, 7, Input<Floating>),
-    PC8: (pc8, 8, Input<Floating>),
-    PC9: (pc9, 9, Input<Floating>),
-    PC10: (pc10, 10, Input<Floating>),
-    PC11: (pc11, 11, Input<Floating>),
-    PC12: (pc12, 12, Input<Floating>),
-    PC13: (pc13, 13, Input<Floating>),
-    PC14: (pc14, 14, Input<Floating>),
-    PC15: (pc15, 15, Input<Floating>),
+gpio!(GPIOC, gpioc, gpiocen, PC, 2, [
+    PC0: (pc0, 0, Input),
+    PC1: (pc1, 1, Input),
+    PC2: (pc2, 2, Input),
+    PC3: (pc3, 3, Input),
+    PC4: (pc4, 4, Input),
+    PC5: (pc5, 5, Input),
+    PC6: (pc6, 6, Input),
+    PC7: (pc7, 7, Input),
+    PC8: (pc8, 8, Input),
+    PC9: (pc9, 9, Input),
+    PC10: (pc10, 10, Input),
+    PC11: (pc11, 11, Input),
+    PC12: (pc12, 12, Input),
+    PC13: (pc13, 13, Input),
+    PC14: (pc14, 14, Input),
+    PC15: (pc15, 15, Input),
 ]);
 
-gpio!(GPIOD, gpiod, gpioden, PD, [
-    PD0: (pd0, 0, Input<Floating>),
-    PD1: (pd1, 1, Input<Floating>),
-    PD2: (pd2, 2, Input<Floating>),
-    PD3: (pd3, 3, Input<Floating>),
-    PD4: (pd4, 4, Input<Floating>),
-    PD5: (pd5, 5, Input<Floating>),
-    PD6: (pd6, 6, Input<Floating>),
-    PD7: (pd7, 7, Input<Floating>),
-    PD8: (pd8, 8, Input<Floating>),
-    PD9: (pd9, 9, Input<Floating>),
-    PD10: (pd10, 10, Input<Floating>),
-    PD11: (pd11, 11, Input<Floating>),
-    PD12: (pd12, 12, Input<Floating>),
-    PD13: (pd13, 13, Input<Floating>),
-    PD14: (pd14, 14, Input<Floating>),
-    PD15: (pd15, 15, Input<Floating>),
+gpio!(GPIOD, gpiod, gpioden, PD, 3, [
+    PD0: (pd0, 0, Input),
+    PD1: (pd1, 1, Input),
+    PD2: (pd2, 2, Input),
+    PD3: (pd3, 3, Input),
+    PD4: (pd4, 4, Input),
+    PD5: (pd5, 5, Input),
+    PD6: (pd6, 6, Input),
+    PD7: (pd7, 7, Input),
+    PD8: (pd8, 8, Input),
+    PD9: (pd9, 9, Input),
+    PD10: (pd10, 10, Input),
+    PD11: (pd11, 11, Input),
+    PD12: (pd12, 12, Input),
+    PD13: (pd13, 13, Input),
+    PD14: (pd14, 14, Input),
+    PD15: (pd15, 15, Input),
 ]);
 
-gpio!(GPIOE, gpioe, gpioeen, PE, [
-    PE0: (pe0, 0, Input<Floating>),
-    PE1: (pe1, 1, Input<Floating>),
-    PE2: (pe2, 2, Input<Floating>),
-    PE3: (pe3, 3, Input<Floating>),
-    PE4: (pe4, 4, Input<Floating>),
-    PE5: (pe5, 5, Input<Floating>),
-    PE6: (pe6, 6, Input<Floating>),
-    PE7: (pe7, 7, Input<Floating>),
-    PE8: (pe8, 8, Input<Floating>),
-    PE9: (pe9, 9, Input<Floating>),
-    PE10: (pe10, 10, Input<Floating>),
-    PE11: (pe11, 11, Input<Floating>),
-    PE12: (pe12, 12, Input<Floating>),
-    PE13: (pe13, 13, Input<Floating>),
-    PE14: (pe14, 14, Input<Floating>),
-    PE15: (pe15, 15, Input<Floating>),
+gpio!(GPIOE, gpioe, gpioeen, PE, 4, [
+    PE0: (pe0, 0, Input),
+    PE1: (pe1, 1, Input),
+    PE2: (pe2, 2, Input),
+    PE3: (pe3, 3, Input),
+    PE4: (pe4, 4, Input),
+    PE5: (pe5, 5, Input),
+    PE6: (pe6, 6, Input),
+    PE7: (pe7, 7, Input),
+    PE8: (pe8, 8, Input),
+    PE9: (pe9, 9, Input),
+    PE10: (pe10, 10, Input),
+    PE11: (pe11, 11, Input),
+    PE12: (pe12, 12, Input),
+    PE13: (pe13, 13, Input),
+    PE14: (pe14, 14, Input),
+    PE15: (pe15, 15, Input),
 ]);
 
-gpio!(GPIOF, gpiof, gpiofen, PF, [
-    PF0: (pf0, 0, Input<Floating>),
-    PF1: (pf1, 1, Input<Floating>),
-    PF2: (pf2, 2, Input<Floating>),
-    PF3: (pf3, 3, Input<Floating>),
-    PF4: (pf4, 4, Input<Floating>),
-    PF5: (pf5, 5, Input<Floating>),
-    PF6: (pf6, 6, Input<Floating>),
-    PF7: (pf7, 7, Input<Floating>),
-    PF8: (pf8, 8, Input<Floating>),
-    PF9: (pf9, 9, Input<Floating>),
-    PF10: (pf10, 10, Input<Floating>),
-    PF11: (pf11, 11, Input<Floating>),
-    PF12: (pf12, 12, Input<Floating>),
-    PF13: (pf13, 13, Input<Floating>),
-    PF14: (pf14, 14, Input<Floating>),
-    PF15: (pf15, 15, Input<Floating>),
+#[cfg(feature = "gpio-fg")]
+gpio!(GPIOF, gpiof, gpiofen, PF, 5, [
+    PF0: (pf0, 0, Input),
+    PF1: (pf1, 1, Input),
+    PF2: (pf2, 2, Input),
+    PF3: (pf3, 3, Input),
+    PF4: (pf4, 4, Input),
+    PF5: (pf5, 5, Input),
+    PF6: (pf6, 6, Input),
+    PF7: (pf7, 7, Input),
+    PF8: (pf8, 8, Input),
+    PF9: (pf9, 9, Input),
+    PF10: (pf10, 10, Input),
+    PF11: (pf11, 11, Input),
+    PF12: (pf12, 12, Input),
+    PF13: (pf13, 13, Input),
+    PF14: (pf14, 14, Input),
+    PF15: (pf15, 15, Input),
 ]);
 
-gpio!(GPIOG, gpiog, gpiogen, PG, [
-    PG0: (pg0, 0, Input<Floating>),
-    PG1: (pg1, 1, Input<Floating>),
-    PG2: (pg2, 2, Input<Floating>),
-    PG3: (pg3, 3, Input<Floating>),
-    PG4: (pg4, 4, Input<Floating>),
-    PG5: (pg5, 5, Input<Floating>),
-    PG6: (pg6, 6, Input<Floating>),
-    PG7: (pg7, 7, Input<Floating>),
-    PG8: (pg8, 8, Input<Floating>),
-    PG9: (pg9, 9, Input<Floating>),
-    PG10: (pg10, 10, Input<Floating>),
-    PG11: (pg11, 11, Input<Floating>),
-    PG12: (pg12, 12, Input<Floating>),
-    PG13: (pg13, 13, Input<Floating>),
-    PG14: (pg14, 14, Input<Floating>),
-    PG15: (pg15, 15, Input<Floating>),
+#[cfg(feature = "gpio-fg")]
+gpio!(GPIOG, gpiog, gpiogen, PG, 6, [
+    PG0: (pg0, 0, Input),
+    PG1: (pg1, 1, Input),
+    PG2: (pg2, 2, Input),
+    PG3: (pg3, 3, Input),
+    PG4: (pg4, 4, Input),
+    PG5: (pg5, 5, Input),
+    PG6: (pg6, 6, Input),
+    PG7: (pg7, 7, Input),
+    PG8: (pg8, 8, Input),
+    PG9: (pg9, 9, Input),
+    PG10: (pg10, 10, Input),
+    PG11: (pg11, 11, Input),
+    PG12: (pg12, 12, Input),
+    PG13: (pg13, 13, Input),
+    PG14: (pg14, 14, Input),
+    PG15: (pg15, 15, Input),
 ]);
 
-gpio!(GPIOH, gpioh, gpiohen, PH, [
-    PH0: (ph0, 0, Input<Floating>),
-    PH1: (ph1, 1, Input<Floating>),
-    PH2: (ph2, 2, Input<Floating>),
-    PH3: (ph3, 3, Input<Floating>),
-    PH4: (ph4, 4, Input<Floating>),
-    PH5: (ph5, 5, Input<Floating>),
-    PH6: (ph6, 6, Input<Floating>),
-    PH7: (ph7, 7, Input<Floating>),
-    PH8: (ph8, 8, Input<Floating>),
-    PH9: (ph9, 9, Input<Floating>),
-    PH10: (ph10, 10, Input<Floating>),
-    PH11: (ph11, 11, Input<Floating>),
-    PH12: (ph12, 12, Input<Floating>),
-    PH13: (ph13, 13, Input<Floating>),
-    PH14: (ph14, 14, Input<Floating>),
-    PH15: (ph15, 15, Input<Floating>),
+gpio!(GPIOH, gpioh, gpiohen, PH, 7, [
+    PH0: (ph0, 0, Input),
+    PH1: (ph1, 1, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH2: (ph2, 2, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH3: (ph3, 3, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH4: (ph4, 4, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH5: (ph5, 5, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH6: (ph6, 6, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH7: (ph7, 7, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH8: (ph8, 8, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH9: (ph9, 9, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH10: (ph10, 10, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH11: (ph11, 11, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH12: (ph12, 12, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH13: (ph13, 13, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH14: (ph14, 14, Input),
+    #[cfg(feature = "gpio-hi")]
+    PH15: (ph15, 15, Input),
 ]);
 
-gpio!(GPIOI, gpioi, gpioien, PI, [
-    PI0: (pi0, 0, Input<Floating>),
-    PI1: (pi1, 1, Input<Floating>),
-    PI2: (pi2, 2, Input<Floating>),
-    PI3: (pi3, 3, Input<Floating>),
-    PI4: (pi4, 4, Input<Floating>),
-    PI5: (pi5, 5, Input<Floating>),
-    PI6: (pi6, 6, Input<Floating>),
-    PI7: (pi7, 7, Input<Floating>),
-    PI8: (pi8, 8, Input<Floating>),
-    PI9: (pi9, 9, Input<Floating>),
-    PI10: (pi10, 10, Input<Floating>),
-    PI11: (pi11, 11, Input<Floating>),
-    PI12: (pi12, 12, Input<Floating>),
-    PI13: (pi13, 13, Input<Floating>),
-    PI14: (pi14, 14, Input<Floating>),
-    PI15: (pi15, 15, Input<Floating>),
+#[cfg(feature = "gpio-hi")]
+gpio!(GPIOI, gpioi, gpioien, PI, 8, [
+    PI0: (pi0, 0, Input),
+    PI1: (pi1, 1, Input),
+    PI2: (pi2, 2, Input),
+    PI3: (pi3, 3, Input),
+    PI4: (pi4, 4, Input),
+    PI5: (pi5, 5, Input),
+    PI6: (pi6, 6, Input),
+    PI7: (pi7, 7, Input),
+    PI8: (pi8, 8, Input),
+    PI9: (pi9, 9, Input),
+    PI10: (pi10, 10, Input),
+    PI11: (pi11, 11, Input),
+    #[cfg(feature = "gpio-jk")]
+    PI12: (pi12, 12, Input),
+    #[cfg(feature = "gpio-jk")]
+    PI13: (pi13, 13, Input),
+    #[cfg(feature = "gpio-jk")]
+    PI14: (pi14, 14, Input),
+    #[cfg(feature = "gpio-jk")]
+    PI15: (pi15, 15, Input),
 ]);
 
-gpio!(GPIOJ, gpioj, gpiojen, PJ, [
-    PJ0: (pj0, 0, Input<Floating>),
-    PJ1: (pj1, 1, Input<Floating>),
-    PJ2: (pj2, 2, Input<Floating>),
-    PJ3: (pj3, 3, Input<Floating>),
-    PJ4: (pj4, 4, Input<Floating>),
-    PJ5: (pj5, 5, Input<Floating>),
-    PJ6: (pj6, 6, Input<Floating>),
-    PJ7: (pj7, 7, Input<Floating>),
-    PJ8: (pj8, 8, Input<Floating>),
-    PJ9: (pj9, 9, Input<Floating>),
-    PJ10: (pj10, 10, Input<Floating>),
-    PJ11: (pj11, 11, Input<Floating>),
-    PJ12: (pj12, 12, Input<Floating>),
-    PJ13: (pj13, 13, Input<Floating>),
-    PJ14: (pj14, 14, Input<Floating>),
-    PJ15: (pj15, 15, Input<Floating>),
+#[cfg(feature = "gpio-jk")]
+gpio!(GPIOJ, gpioj, gpiojen, PJ, 9, [
+    PJ0: (pj0, 0, Input),
+    PJ1: (pj1, 1, Input),
+    PJ2: (pj2, 2, Input),
+    PJ3: (pj3, 3, Input),
+    PJ4: (pj4, 4, Input),
+    PJ5: (pj5, 5, Input),
+    PJ6: (pj6, 6, Input),
+    PJ7: (pj7, 7, Input),
+    PJ8: (pj8, 8, Input),
+    PJ9: (pj9, 9, Input),
+    PJ10: (pj10, 10, Input),
+    PJ11: (pj11, 11, Input),
+    PJ12: (pj12, 12, Input),
+    PJ13: (pj13, 13, Input),
+    PJ14: (pj14, 14, Input),
+    PJ15: (pj15, 15, Input),
 ]);
 
-gpio!(GPIOK, gpiok, gpioken, PK, [
-    PK0: (pk0, 0, Input<Floating>),
-    PK1: (pk1, 1, Input<Floating>),
-    PK2: (pk2, 2, Input<Floating>),
-    PK3: (pk3, 3, Input<Floating>),
-    PK4: (pk4, 4, Input<Floating>),
-    PK5: (pk5, 5, Input<Floating>),
-    PK6: (pk6, 6, Input<Floating>),
-    PK7: (pk7, 7, Input<Floating>),
+#[cfg(feature = "gpio-jk")]
+gpio!(GPIOK, gpiok, gpioken, PK, 10, [
+    PK0: (pk0, 0, Input),
+    PK1: (pk1, 1, Input),
+    PK2: (pk2, 2, Input),
+    PK3: (pk3, 3, Input),
+    PK4: (pk4, 4, Input),
+    PK5: (pk5, 5, Input),
+    PK6: (pk6, 6, Input),
+    PK7: (pk7, 7, Input),
 ]);
\ No newline at end of file
@@ -1,377 +1,1689 @@
 //! Inter Integrated Circuit implementation
 // I2C implementation, largely taken over from japaric's HAL like so many other features here
 
-use crate::gpio::gpioa::PA8;
-use crate::gpio::gpiob::{PB10, PB11, PB6, PB7, PB8, PB9};
+use core::cmp;
+use core::iter;
+
+use crate::gpio::gpioa::{PA8, PA9};
+use crate::gpio::gpiob::{PB10, PB11, PB12, PB5, PB6, PB7, PB8, PB9};
 use crate::gpio::gpioc::PC9;
-use crate::gpio::gpiod::{PD12, PD13};
-use crate::gpio::gpiof::{PF0, PF1, PF14, PF15};
-use crate::gpio::gpioh::{PH11, PH12, PH4, PH5, PH7, PH8};
-use crate::gpio::{AF4, Output, OpenDrain};
-use crate::rcc::{Clocks, APB1L, APB4};
-use crate::time::Hertz;
+use crate::gpio::gpiod::{PD11, PD12, PD13};
+#[cfg(feature = "gpio-fg")]
+use crate::gpio::gpiof::{PF0, PF1, PF13, PF14, PF15, PF2};
+#[cfg(feature = "gpio-hi")]
+use crate::gpio::gpioh::{PH10, PH11, PH12, PH4, PH5, PH6, PH7, PH8, PH9};
+use crate::gpio::{AF4, AF6, Alternate, Locked, OpenDrain, RawPin};
+use crate::dma::{self, Stream};
+use crate::rcc::{self, Clocks, APB1L, APB4};
+use crate::time::{Hertz, KiloHertz, MegaHertz, MilliSeconds, MonoTimer};
+use hal::blocking::delay::DelayUs;
 use hal::blocking::i2c::{Write, WriteRead, Read};
-use stm32h7::stm32h7x3::{I2C1, I2C2, I2C3, I2C4};
+use nb::block;
+use stm32h7::stm32h7x3::{i2c1, syscfg, I2C1, I2C2, I2C3, I2C4, SYSCFG};
 use cast::{u8, u16};
 
 
 /// I2C error
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Error {
     /// Bus error
     Bus,
     /// Arbitration loss
     Arbitration,
-    // Overrun, // slave mode only
-    // Pec, // SMBUS mode only
-    // Timeout, // SMBUS mode only
-    // Alert, // SMBUS mode only
+    /// The slave did not acknowledge its address or a data byte
+    Nack,
+    /// The transfer did not finish within the configured software timeout, or SCL was
+    /// held low for longer than the SMBus timeout
+    Timeout,
+    /// A received byte was not read in time or no byte was written in time, slave mode
+    /// with clock stretching disabled only
+    Overrun,
+    /// The received PEC did not match, SMBus mode only
+    Pec,
+    /// No device answered an SMBus alert, SMBus mode only
+    Alert,
+    /// The byte count of an SMBus block was 0 or larger than the buffer, SMBus mode only
+    BlockLength,
+    /// The kernel clock is stopped or its frequency unknown, select another one with `CCIPR`
+    KernelClock,
+    /// The address does not fit into 7 or 10 bits
+    Address,
     #[doc(hidden)]
     _Extensible,
 }
 
+/// Maximum number of bytes the NBYTES field can hold
+const MAX_NBYTES: usize = 255;
+
 /// A trait to represent the SCL Pin of an I2C Port
 pub unsafe trait SclPin<I2C> {}
 
 /// A trait to represent the SDL Pin of an I2C Port
 pub unsafe trait SdaPin<I2C> {}
 
+/// A trait to represent the SMBALERT Pin of an I2C Port
+pub unsafe trait SmbAlertPin<I2C> {}
+
+unsafe impl<I2C, PIN: SclPin<I2C>> SclPin<I2C> for Locked<PIN> {}
+unsafe impl<I2C, PIN: SdaPin<I2C>> SdaPin<I2C> for Locked<PIN> {}
+unsafe impl<I2C, PIN: SmbAlertPin<I2C>> SmbAlertPin<I2C> for Locked<PIN> {}
+
 // I2C1_SCL
-unsafe impl SclPin<I2C1> for PB8<Output<OpenDrain>, AF4> {}
+unsafe impl SclPin<I2C1> for PB6<Alternate<AF4, OpenDrain>> {}
+unsafe impl SclPin<I2C1> for PB8<Alternate<AF4, OpenDrain>> {}
 
 // I2C1_SDA
-unsafe impl SdaPin<I2C1> for PB7<Output<OpenDrain>, AF4> {}
-unsafe impl SdaPin<I2C1> for PB9<Output<OpenDrain>, AF4> {}
+unsafe impl SdaPin<I2C1> for PB7<Alternate<AF4, OpenDrain>> {}
+unsafe impl SdaPin<I2C1> for PB9<Alternate<AF4, OpenDrain>> {}
 
 // I2C2_SCL
-unsafe impl SclPin<I2C2> for PB10<Output<OpenDrain>, AF4> {}
-unsafe impl SclPin<I2C2> for PF1<Output<OpenDrain>, AF4> {}
-unsafe impl SclPin<I2C2> for PH4<Output<OpenDrain>, AF4> {}
+unsafe impl SclPin<I2C2> for PB10<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl SclPin<I2C2> for PF1<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl SclPin<I2C2> for PH4<Alternate<AF4, OpenDrain>> {}
 
 // I2C2_SDA
-unsafe impl SdaPin<I2C2> for PB11<Output<OpenDrain>, AF4> {}
-unsafe impl SdaPin<I2C2> for PF0<Output<OpenDrain>, AF4> {}
-unsafe impl SdaPin<I2C2> for PH5<Output<OpenDrain>, AF4> {}
+unsafe impl SdaPin<I2C2> for PB11<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl SdaPin<I2C2> for PF0<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl SdaPin<I2C2> for PH5<Alternate<AF4, OpenDrain>> {}
 
 // I2C3_SCL
-unsafe impl SclPin<I2C3> for PA8<Output<OpenDrain>, AF4> {}
-unsafe impl SclPin<I2C3> for PH7<Output<OpenDrain>, AF4> {}
+unsafe impl SclPin<I2C3> for PA8<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl SclPin<I2C3> for PH7<Alternate<AF4, OpenDrain>> {}
 
 // I2C3_SDA
-unsafe impl SdaPin<I2C3> for PC9<Output<OpenDrain>, AF4> {}
-unsafe impl SdaPin<I2C3> for PH8<Output<OpenDrain>, AF4> {}
+unsafe impl SdaPin<I2C3> for PC9<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl SdaPin<I2C3> for PH8<Alternate<AF4, OpenDrain>> {}
 
 // I2C4_SCL
-unsafe impl SclPin<I2C4> for PD12<Output<OpenDrain>, AF4> {}
-unsafe impl SclPin<I2C4> for PF14<Output<OpenDrain>, AF4> {}
-unsafe impl SclPin<I2C4> for PH11<Output<OpenDrain>, AF4> {}
-unsafe impl SclPin<I2C4> for PB6<Output<OpenDrain>, AF4> {}
-unsafe impl SclPin<I2C4> for PB8<Output<OpenDrain>, AF4> {}
+unsafe impl SclPin<I2C4> for PD12<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl SclPin<I2C4> for PF14<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl SclPin<I2C4> for PH11<Alternate<AF4, OpenDrain>> {}
+unsafe impl SclPin<I2C4> for PB6<Alternate<AF6, OpenDrain>> {}
+unsafe impl SclPin<I2C4> for PB8<Alternate<AF6, OpenDrain>> {}
 
 // I2C4_SDA
-unsafe impl SdaPin<I2C4> for PB7<Output<OpenDrain>, AF4> {}
-unsafe impl SdaPin<I2C4> for PB9<Output<OpenDrain>, AF4> {}
-unsafe impl SdaPin<I2C4> for PD13<Output<OpenDrain>, AF4> {}
-unsafe impl SdaPin<I2C4> for PF15<Output<OpenDrain>, AF4> {}
-unsafe impl SdaPin<I2C4> for PH12<Output<OpenDrain>, AF4> {}
+unsafe impl SdaPin<I2C4> for PB7<Alternate<AF6, OpenDrain>> {}
+unsafe impl SdaPin<I2C4> for PB9<Alternate<AF6, OpenDrain>> {}
+unsafe impl SdaPin<I2C4> for PD13<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl SdaPin<I2C4> for PF15<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl SdaPin<I2C4> for PH12<Alternate<AF4, OpenDrain>> {}
+
+// I2C1_SMBA
+unsafe impl SmbAlertPin<I2C1> for PB5<Alternate<AF4, OpenDrain>> {}
+
+// I2C2_SMBA
+unsafe impl SmbAlertPin<I2C2> for PB12<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl SmbAlertPin<I2C2> for PF2<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl SmbAlertPin<I2C2> for PH6<Alternate<AF4, OpenDrain>> {}
+
+// I2C3_SMBA
+unsafe impl SmbAlertPin<I2C3> for PA9<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl SmbAlertPin<I2C3> for PH9<Alternate<AF4, OpenDrain>> {}
+
+// I2C4_SMBA
+unsafe impl SmbAlertPin<I2C4> for PD11<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl SmbAlertPin<I2C4> for PF13<Alternate<AF4, OpenDrain>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl SmbAlertPin<I2C4> for PH10<Alternate<AF4, OpenDrain>> {}
 
 pub struct I2c<I2C, PINS> {
     i2c: I2C,
     pins: PINS,
+    /// Software timeout, the timer to measure it and its length in timer ticks
+    timeout: Option<(MonoTimer, u32)>,
+    /// Append a PEC byte to the last segment of every transaction, SMBus mode only
+    pec: bool,
+}
+
+/// I2C bus configuration
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Bus frequency, up to 1 MHz
+    pub frequency: Hertz,
+    /// Enable the analog noise filter, which suppresses spikes shorter than 50 ns
+    pub analog_filter: bool,
+    /// Length of the digital noise filter in kernel clock periods, 0 (off) to 15
+    pub digital_filter: u8,
+    /// Enable the Fast-mode Plus drive of the pins, required above 400 kHz
+    pub fast_mode_plus: bool,
+}
+
+impl Config {
+    /// Configuration with the analog noise filter and Fast-mode Plus drive above 400 kHz
+    pub fn new<F>(frequency: F) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        let frequency = frequency.into();
+
+        Config {
+            frequency,
+            analog_filter: true,
+            digital_filter: 0,
+            fast_mode_plus: frequency.0 > 400_000,
+        }
+    }
+}
+
+impl From<Hertz> for Config {
+    fn from(frequency: Hertz) -> Self {
+        Config::new(frequency)
+    }
+}
+
+impl From<KiloHertz> for Config {
+    fn from(frequency: KiloHertz) -> Self {
+        Config::new(frequency)
+    }
+}
+
+impl From<MegaHertz> for Config {
+    fn from(frequency: MegaHertz) -> Self {
+        Config::new(frequency)
+    }
+}
+
+/// Alert response address an SMBus device answers with its own address
+const ALERT_RESPONSE_ADDRESS: u8 = 0x0c;
+
+/// SMBus host configuration
+#[derive(Clone, Copy)]
+pub struct SmbusConfig {
+    /// Append a PEC to every transaction and check the PEC of received ones
+    pub pec: bool,
+    /// Abort transfers with `Error::Timeout` when SCL is held low for longer than 25 ms
+    /// or a slave stretches a message by more than 10 ms in total
+    pub timeout: bool,
+}
+
+/// I2C peripheral operating as SMBus host
+pub struct Smbus<I2C, PINS, ALERT = ()> {
+    i2c: I2c<I2C, PINS>,
+    alert: ALERT,
+}
+
+/// Address of an I2C slave
+///
+/// Transfers to an address out of range fail with `Error::Address`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Address {
+    /// 7-bit address
+    SevenBit(u8),
+    /// 10-bit address
+    TenBit(u16),
+}
+
+impl From<u8> for Address {
+    fn from(address: u8) -> Self {
+        Address::SevenBit(address)
+    }
+}
+
+impl Address {
+    /// Creates a 7-bit address, from 0x00 to 0x7f
+    pub fn seven_bit(address: u8) -> Self {
+        assert!(address <= 0x7f);
+
+        Address::SevenBit(address)
+    }
+
+    /// Creates a 10-bit address, from 0x000 to 0x3ff
+    pub fn ten_bit(address: u16) -> Self {
+        assert!(address <= 0x3ff);
+
+        Address::TenBit(address)
+    }
+
+    /// Returns the address as SADD or OA1 expects it and whether it has 10 bits
+    fn bits(self) -> Result<(u16, bool), Error> {
+        match self {
+            Address::SevenBit(address) if address < 0x80 => Ok((u16(address) << 1, false)),
+            Address::TenBit(address) if address < 0x400 => Ok((address, true)),
+            _ => Err(Error::Address),
+        }
+    }
+}
+
+/// Segment of an I2C transaction
+pub enum Operation<'a> {
+    /// Read bytes into the buffer
+    Read(&'a mut [u8]),
+    /// Write the bytes
+    Write(&'a [u8]),
+}
+
+/// Second 7-bit own address of an I2C slave
+#[derive(Clone, Copy)]
+pub struct OwnAddress2 {
+    /// The address
+    pub address: u8,
+    /// Number of low address bits that are ignored when matching, 0 to 7
+    pub mask: u8,
+}
+
+/// I2C slave configuration
+#[derive(Clone, Copy)]
+pub struct SlaveConfig {
+    /// Primary own address
+    pub address1: Address,
+    /// Optional secondary own address
+    pub address2: Option<OwnAddress2>,
+    /// Acknowledge the general call address 0x00
+    pub general_call: bool,
+    /// Hold SCL low while the slave is not ready. Without it, every received byte has to
+    /// be read and every byte to send has to be written before the next one is clocked.
+    pub clock_stretching: bool,
+}
+
+impl SlaveConfig {
+    /// Configuration with a 7-bit address, no general call and clock stretching enabled
+    pub fn new(address: u8) -> Self {
+        SlaveConfig {
+            address1: Address::SevenBit(address),
+            address2: None,
+            general_call: false,
+            clock_stretching: true,
+        }
+    }
+}
+
+/// Direction of a transfer, seen from the master
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    /// The master writes, the slave receives
+    Write,
+    /// The master reads, the slave transmits
+    Read,
+}
+
+/// Events of an I2C slave
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlaveEvent {
+    /// One of the own addresses was matched, `address` holds the 7 received address bits
+    /// (the header for 10-bit addresses)
+    AddressMatched { address: u8, direction: Direction },
+    /// A byte was received from the master
+    RxByte(u8),
+    /// The master wants to read a byte, answer with `I2cSlave::write_byte`
+    TxRequest,
+    /// The transfer was ended with a STOP
+    Stop,
+}
+
+/// I2C peripheral operating in slave mode
+pub struct I2cSlave<I2C, PINS> {
+    i2c: I2C,
+    pins: PINS,
 }
 
 macro_rules! busy_wait {
     ($i2c:expr, $flag:ident) => {
-        loop {
-            let isr = $i2c.isr.read();
-
-            if isr.berr().bit_is_set() {
-                return Err(Error::Bus);
-            } else if isr.arlo().bit_is_set() {
-                return Err(Error::Arbitration);
-            } else if isr.$flag().bit_is_set() {
-                break;
-            } else {
-                // try again
-            }
-        }
+        $i2c.wait(|isr| isr.$flag().bit_is_set())?
+    };
+}
+
+/// Computes PRESC, SCLL, SCLH, SDADEL and SCLDEL for a bus running at `freq`
+///
+/// The digital noise filter delays SCL and SDA by `dnf` kernel clock periods.
+fn timing(i2cclk: u32, freq: u32, dnf: u32) -> (u8, u8, u8, u8, u8) {
+    // experimental, not sure if this works
+    // TODO review compliance with the timing requirements of I2C
+    // t_I2CCLK = 1 / PCLK1
+    // t_PRESC  = (PRESC + 1) * t_I2CCLK
+    // t_SCLL   = (SCLL + 1) * t_PRESC
+    // t_SCLH   = (SCLH + 1) * t_PRESC
+    //
+    // t_SYNC1 + t_SYNC2 > (4 + 2 * DNF) * t_I2CCLK
+    // t_SCL ~= t_SYNC1 + t_SYNC2 + t_SCLL + t_SCLH
+    let ratio = i2cclk / freq - 4 - 2 * dnf;
+    let (presc, scll, sclh, sdadel, scldel) = if freq > 100_000 {
+        // fast-mode or fast-mode plus
+        // here we pick SCLL + 1 = 2 * (SCLH + 1)
+        let presc = ratio / 387;
+
+        let sclh = ((ratio / (presc + 1)) - 3) / 3;
+        let scll = 2 * (sclh + 1) - 1;
+
+        let (sdadel, scldel) = if freq > 400_000 {
+            // fast-mode plus
+            let sdadel = 0;
+            let scldel = i2cclk / 4_000_000 / (presc + 1) - 1;
+
+            (sdadel, scldel)
+        } else {
+            // fast-mode
+            let sdadel = (i2cclk / 8_000_000).saturating_sub(dnf) / (presc + 1);
+            let scldel = i2cclk / 2_000_000 / (presc + 1) - 1;
+
+            (sdadel, scldel)
+        };
+
+        (presc, scll, sclh, sdadel, scldel)
+    } else {
+        // standard-mode
+        // here we pick SCLL = SCLH
+        let presc = ratio / 514;
+        let sclh = ((ratio / (presc + 1)) - 2) / 2;
+        let scll = sclh;
+
+        let sdadel = (i2cclk / 2_000_000).saturating_sub(dnf) / (presc + 1);
+        let scldel = i2cclk / 800_000 / (presc + 1) - 1;
+
+        (presc, scll, sclh, sdadel, scldel)
     };
+
+    let presc = u8(presc).unwrap();
+    //assert!(presc < 16);
+    let scldel = u8(scldel).unwrap();
+    //assert!(scldel < 16);
+    let sdadel = u8(sdadel).unwrap();
+    //assert!(sdadel < 16);
+    let sclh = u8(sclh).unwrap();
+    let scll = u8(scll).unwrap();
+
+    (presc, scll, sclh, sdadel, scldel)
+}
+
+/// Iterator over the 7-bit addresses that acknowledge, see `I2c::scan`
+pub struct Scan<'a, I2C, PINS> {
+    i2c: &'a mut I2c<I2C, PINS>,
+    next: u8,
+}
+
+/// First 7-bit address that is not reserved
+const FIRST_ADDRESS: u8 = 0x08;
+/// Last 7-bit address that is not reserved
+const LAST_ADDRESS: u8 = 0x77;
+
+/// Segment of a non-blocking transfer that is in progress
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    Write,
+    Read,
+}
+
+/// Non-blocking I2C transfer, driven by the I2C event and error interrupts or by DMA
+///
+/// The bytes are written first, then the buffer is read after a repeated START. `poll`
+/// has to be called from both interrupt handlers, or in a loop, until it returns the
+/// result of the transfer.
+pub struct Transfer<I2C, PINS, STREAMS = ()> {
+    i2c: I2c<I2C, PINS>,
+    addr: Address,
+    bytes: &'static [u8],
+    buffer: &'static mut [u8],
+    streams: STREAMS,
+    phase: Phase,
+    /// Bytes of the current segment moved by software
+    index: usize,
+    /// NBYTES chunks of the current segment that are done
+    reloads: usize,
+    /// Error to report once the STOP is generated
+    error: Option<Error>,
+    result: Option<Result<(), Error>>,
+    callback: Option<fn(Result<(), Error>)>,
+}
+
+impl<I2C, PINS, STREAMS> Transfer<I2C, PINS, STREAMS> {
+    /// Sets a function that is called with the result once the transfer is done
+    pub fn on_complete(&mut self, callback: fn(Result<(), Error>)) {
+        self.callback = Some(callback);
+    }
+
+    /// Returns whether the transfer is done
+    pub fn is_done(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// Releases the I2C peripheral, the buffers and the DMA streams
+    ///
+    /// Panics if the transfer is not done yet.
+    pub fn free(self) -> (I2c<I2C, PINS>, &'static [u8], &'static mut [u8], STREAMS) {
+        assert!(self.is_done());
+
+        (self.i2c, self.bytes, self.buffer, self.streams)
+    }
+
+    /// Calls the callback with the result of the transfer
+    fn notify(&self) {
+        if let (Some(callback), Some(result)) = (self.callback, self.result) {
+            callback(result);
+        }
+    }
+
+    /// Length of the current segment
+    fn segment_len(&self) -> usize {
+        match self.phase {
+            Phase::Write => self.bytes.len(),
+            Phase::Read => self.buffer.len(),
+        }
+    }
+}
+
+/// Sets up the timing and the noise filters of a disabled I2C peripheral
+fn configure(i2c: &i2c1::RegisterBlock, config: &Config, i2cclk: Hertz) {
+    let freq = config.frequency.0;
+
+    assert!(freq <= 1_000_000);
+    assert!(config.digital_filter < 16);
+
+    let (presc, scll, sclh, sdadel, scldel) =
+        timing(i2cclk.0, freq, u32::from(config.digital_filter));
+
+    i2c.timingr.write(|w|
+        w.presc()
+            .bits(presc)
+            .scll()
+            .bits(scll)
+            .sclh()
+            .bits(sclh)
+            .sdadel()
+            .bits(sdadel)
+            .scldel()
+            .bits(scldel)
+    );
+
+    // The filters can only be changed while the peripheral is disabled
+    i2c.cr1.modify(|_, w| {
+        w.anfoff()
+            .bit(!config.analog_filter)
+            .dnf()
+            .bits(config.digital_filter)
+    });
+}
+
+/// Switches the Fast-mode Plus drive of an I2C peripheral with the PMCR bit written by `fmp`
+fn set_fast_mode_plus<F>(fmp: F)
+where
+    F: FnOnce(&mut syscfg::pmcr::W) -> &mut syscfg::pmcr::W,
+{
+    rcc::enable_syscfg();
+
+    // NOTE(unsafe) read-modify-write within a critical section, PMCR is not used anywhere
+    // else
+    cortex_m::interrupt::free(|_| unsafe {
+        (*SYSCFG::ptr()).pmcr.modify(|_, w| fmp(w))
+    });
 }
 
 macro_rules! i2c {
-    ($($I2CX:ident: ($i2cX:ident, $i2cXen:ident, $i2cXrst:ident, $APBX:ident, $PCLKX:ident),)+) => {
+    ($($I2CX:ident: ($i2cX:ident, $i2cXen:ident, $i2cXrst:ident, $APBX:ident, $ker_ck:ident, $i2cXfmp:ident),)+) => {
         $(
             impl<SCL, SDA> I2c<$I2CX, (SCL, SDA)> {
                 /// Basically a new function for the I2C peripheral
-                pub fn $i2cX<F> (
+                ///
+                /// `config` is either a bus frequency or a `Config`. The timing is derived
+                /// from the kernel clock selected with `CCIPR`, fails if that clock is not
+                /// running.
+                pub fn $i2cX<C> (
                     i2c: $I2CX,
                     pins: (SCL, SDA),
-                    freq: F,
+                    config: C,
                     clocks: Clocks,
                     apb: &mut $APBX
-                ) -> Self where
-                    F: Into<Hertz>,
+                ) -> Result<Self, Error> where
+                    C: Into<Config>,
                     SCL: SclPin<$I2CX>,
                     SDA: SdaPin<$I2CX>,
                 {
+                    let ker_ck = clocks.$ker_ck().ok_or(Error::KernelClock)?;
+
                     apb.enr().modify(|_, w| w.$i2cXen().set_bit());
                     apb.rstr().modify(|_, w| w.$i2cXrst().set_bit());
                     apb.rstr().modify(|_, w| w.$i2cXrst().clear_bit());
 
-                    let freq = freq.into().0;
-
-                    assert!(freq <= 1_000_000);
-
-                    let i2cclk = clocks.$PCLKX().0;    
+                    let config = config.into();
 
                     // Refer to figure 539 for this:
                     // Clear PE bit in I2C_CR1
-                    unsafe { &(*I2C1::ptr()).cr1.modify(|_, w| w.pe().clear_bit())};
-
-                    // Enable the Analog Noise Filter by setting ANFOFF (Analog Noise Filter OFF) to 0
-                    // This is usually enabled by default but you never know
-                    unsafe { &(*I2C1::ptr()).cr1.modify(|_, w| w.anfoff().clear_bit())};
-
-                    // experimental, not sure if this works
-                                    // TODO review compliance with the timing requirements of I2C
-                    // t_I2CCLK = 1 / PCLK1
-                    // t_PRESC  = (PRESC + 1) * t_I2CCLK
-                    // t_SCLL   = (SCLL + 1) * t_PRESC
-                    // t_SCLH   = (SCLH + 1) * t_PRESC
-                    //
-                    // t_SYNC1 + t_SYNC2 > 4 * t_I2CCLK
-                    // t_SCL ~= t_SYNC1 + t_SYNC2 + t_SCLL + t_SCLH
-                    let ratio = i2cclk / freq - 4;
-                    let (presc, scll, sclh, sdadel, scldel) = if freq > 100_000 {
-                        // fast-mode or fast-mode plus
-                        // here we pick SCLL + 1 = 2 * (SCLH + 1)
-                        let presc = ratio / 387;
-
-                        let sclh = ((ratio / (presc + 1)) - 3) / 3;
-                        let scll = 2 * (sclh + 1) - 1;
-
-                        let (sdadel, scldel) = if freq > 400_000 {
-                            // fast-mode plus
-                            let sdadel = 0;
-                            let scldel = i2cclk / 4_000_000 / (presc + 1) - 1;
-
-                            (sdadel, scldel)
-                        } else {
-                            // fast-mode
-                            let sdadel = i2cclk / 8_000_000 / (presc + 1);
-                            let scldel = i2cclk / 2_000_000 / (presc + 1) - 1;
-
-                            (sdadel, scldel)
-                        };
+                    i2c.cr1.modify(|_, w| w.pe().clear_bit());
 
-                        (presc, scll, sclh, sdadel, scldel)
-                    } else {
-                        // standard-mode
-                        // here we pick SCLL = SCLH
-                        let presc = ratio / 514;
-                        let sclh = ((ratio / (presc + 1)) - 2) / 2;
-                        let scll = sclh;
-
-                        let sdadel = i2cclk / 2_000_000 / (presc + 1);
-                        let scldel = i2cclk / 800_000 / (presc + 1) - 1;
-
-                        (presc, scll, sclh, sdadel, scldel)
-                    };
-
-                    let presc = u8(presc).unwrap();
-                    //assert!(presc < 16);
-                    let scldel = u8(scldel).unwrap();
-                    //assert!(scldel < 16);
-                    let sdadel = u8(sdadel).unwrap();
-                    //assert!(sdadel < 16);
-                    let sclh = u8(sclh).unwrap();
-                    let scll = u8(scll).unwrap();
-
-                    // Configure for "fast mode" (400 KHz)
-                    i2c.timingr.write(|w| 
-                        w.presc()
-                            .bits(presc)
-                            .scll()
-                            .bits(scll)
-                            .sclh()
-                            .bits(sclh)
-                            .sdadel()
-                            .bits(sdadel)
-                            .scldel()
-                            .bits(scldel)
-                    );
+                    configure(&i2c, &config, ker_ck);
+                    set_fast_mode_plus(|w| w.$i2cXfmp().bit(config.fast_mode_plus));
 
                     // Enable the peripheral
-                    i2c.cr1.write(|w| w.pe().set_bit());
-
-                    I2c { i2c, pins}
+                    i2c.cr1.modify(|_, w| w.pe().set_bit());
 
+                    Ok(I2c { i2c, pins, timeout: None, pec: false })
                 }
                 
                 /// Releases the I2C peripheral and associated pins
+                ///
+                /// The Fast-mode Plus drive of the pins is switched off again.
                 pub fn free(self) -> ($I2CX, (SCL, SDA)) {
+                    set_fast_mode_plus(|w| w.$i2cXfmp().clear_bit());
+
                     (self.i2c, self.pins)
                 }
+
+                /// Frees a bus whose SDA line is held low by a slave, e.g. after a reset
+                /// in the middle of a transfer
+                ///
+                /// The pins are temporarily switched to open drain GPIO outputs, SCL is
+                /// clocked up to nine times until the slave releases SDA and a STOP is
+                /// generated. Returns whether SDA was released.
+                pub fn recover_bus<D>(&mut self, delay: &mut D) -> bool
+                where
+                    SCL: RawPin,
+                    SDA: RawPin,
+                    D: DelayUs<u8>,
+                {
+                    // half a clock period at 100 kHz
+                    const HALF_PERIOD: u8 = 5;
+
+                    self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+
+                    let (scl, sda) = &mut self.pins;
+                    let scl_config = (scl.mode(), scl.is_open_drain());
+                    let sda_config = (sda.mode(), sda.is_open_drain());
+
+                    // NOTE(unsafe) the pins are returned to their original configuration
+                    // before the peripheral is enabled again
+                    let released = unsafe {
+                        // Release both lines before handing them to the GPIO
+                        scl.set_output_type(true);
+                        sda.set_output_type(true);
+                        scl.drive(true);
+                        sda.drive(true);
+                        scl.set_mode(0b01);
+                        sda.set_mode(0b01);
+                        delay.delay_us(HALF_PERIOD);
+
+                        for _ in 0..9 {
+                            if sda.level() {
+                                break;
+                            }
+
+                            scl.drive(false);
+                            delay.delay_us(HALF_PERIOD);
+                            scl.drive(true);
+                            delay.delay_us(HALF_PERIOD);
+                        }
+
+                        // STOP: SDA goes high while SCL is high
+                        scl.drive(false);
+                        delay.delay_us(HALF_PERIOD);
+                        sda.drive(false);
+                        delay.delay_us(HALF_PERIOD);
+                        scl.drive(true);
+                        delay.delay_us(HALF_PERIOD);
+                        sda.drive(true);
+                        delay.delay_us(HALF_PERIOD);
+
+                        let released = sda.level();
+
+                        scl.set_mode(scl_config.0);
+                        scl.set_output_type(scl_config.1);
+                        sda.set_mode(sda_config.0);
+                        sda.set_output_type(sda_config.1);
+
+                        released
+                    };
+
+                    self.i2c.cr1.modify(|_, w| w.pe().set_bit());
+
+                    released
+                }
             }
-            impl<PINS> Write for I2c<$I2CX, PINS> {
-                type Error = Error;
 
-                fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
-                    // TODO support transfers of more than 255 bytes
-                    assert!(bytes.len() < 256 && bytes.len() > 0);
+            impl<PINS> I2c<$I2CX, PINS> {
+                /// Sets a software timeout for every flag the driver waits for
+                ///
+                /// Once it expires the peripheral is reset and `Error::Timeout` is returned,
+                /// which keeps a hanging bus from blocking forever.
+                pub fn set_timeout(&mut self, timer: MonoTimer, timeout: MilliSeconds) {
+                    let ticks = timeout.0 * (timer.frequency().0 / 1_000);
+                    self.timeout = Some((timer, ticks));
+                }
 
-                    // START and prepare to send `bytes`
-                    self.i2c.cr2.write(|w| {
-                        w.start()
+                /// Removes the software timeout, the driver waits forever again
+                pub fn clear_timeout(&mut self) {
+                    self.timeout = None;
+                }
+
+                /// Waits until `flag` returns true for the ISR register
+                ///
+                /// Bus errors, arbitration loss and NACKs abort the wait and leave the
+                /// peripheral ready for the next transfer.
+                fn wait<F>(&mut self, flag: F) -> Result<(), Error>
+                where
+                    F: Fn(&i2c1::isr::R) -> bool,
+                {
+                    let start = self.timeout.map(|(timer, _)| timer.now());
+
+                    loop {
+                        let isr = self.i2c.isr.read();
+
+                        if isr.berr().bit_is_set() {
+                            self.clear_flags();
+                            return Err(Error::Bus);
+                        } else if isr.arlo().bit_is_set() {
+                            self.clear_flags();
+                            return Err(Error::Arbitration);
+                        } else if isr.timeout().bit_is_set() {
+                            self.clear_flags();
+                            return Err(Error::Timeout);
+                        } else if isr.pecerr().bit_is_set() {
+                            // The PEC was NACKed, wait for the STOP before reporting it
+                            self.stop_after_nack();
+                            return Err(Error::Pec);
+                        } else if isr.nackf().bit_is_set() {
+                            self.stop_after_nack();
+                            return Err(Error::Nack);
+                        } else if flag(&isr) {
+                            return Ok(());
+                        }
+
+                        if let (Some(start), Some((_, ticks))) = (start, self.timeout) {
+                            if start.elapsed() > ticks {
+                                self.reset();
+                                return Err(Error::Timeout);
+                            }
+                        }
+                    }
+                }
+
+                /// Waits until no other master uses the bus
+                fn wait_bus_free(&mut self) -> Result<(), Error> {
+                    self.wait(|isr| isr.busy().bit_is_clear())
+                }
+
+                /// Makes sure a STOP follows a NACK and cleans up after it
+                fn stop_after_nack(&mut self) {
+                    // A STOP is generated automatically with AUTOEND, otherwise we have to
+                    // request it
+                    if self.i2c.isr.read().stopf().bit_is_clear() {
+                        self.i2c.cr2.modify(|_, w| w.stop().set_bit());
+                    }
+
+                    let start = self.timeout.map(|(timer, _)| timer.now());
+                    while self.i2c.isr.read().stopf().bit_is_clear() {
+                        if let (Some(start), Some((_, ticks))) = (start, self.timeout) {
+                            if start.elapsed() > ticks {
+                                self.reset();
+                                return;
+                            }
+                        }
+                    }
+
+                    self.clear_flags();
+                }
+
+                /// Clears all flags and flushes a pending byte from TXDR
+                fn clear_flags(&mut self) {
+                    self.i2c.icr.write(|w| {
+                        w.addrcf()
                             .set_bit()
-                            .sadd()
-                            .bits(u16(addr << 1 | 0))
-                            .add10().clear_bit()
-                            .rd_wrn()
-                            .clear_bit()
-                            .nbytes()
-                            .bits(bytes.len() as u8)
-                            .autoend()
+                            .nackcf()
+                            .set_bit()
+                            .stopcf()
+                            .set_bit()
+                            .berrcf()
+                            .set_bit()
+                            .arlocf()
+                            .set_bit()
+                            .ovrcf()
+                            .set_bit()
+                            .peccf()
+                            .set_bit()
+                            .timoutcf()
+                            .set_bit()
+                            .alertcf()
                             .set_bit()
                     });
+                    self.i2c.isr.write(|w| w.txe().set_bit());
+                }
 
-                    for byte in bytes {
-                        // Wait until we are allowed to send data (START has been ACKed or last byte
-                        // when through)
-                        busy_wait!(self.i2c, txis);
+                /// Resets the communication state machine and all flags by toggling PE
+                fn reset(&mut self) {
+                    self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+                    // PE has to stay low for at least 3 APB clock cycles
+                    while self.i2c.cr1.read().pe().bit_is_set() {}
+                    self.i2c.cr1.modify(|_, w| w.pe().set_bit());
+                }
 
-                        // put byte on the wire
-                        self.i2c.txdr.write(|w| w.txdata().bits(*byte));
+                /// Returns whether a device acknowledges `addr`
+                ///
+                /// This sends the address with a zero-length write. Fails with
+                /// `Error::Address` if `addr` does not fit into 7 bits.
+                pub fn probe(&mut self, addr: u8) -> Result<bool, Error> {
+                    match self.master_write(Address::SevenBit(addr), &[], false, true) {
+                        Ok(()) => Ok(true),
+                        Err(Error::Nack) => Ok(false),
+                        Err(e) => Err(e),
                     }
+                }
+
+                /// Returns an iterator over the 7-bit addresses that acknowledge
+                ///
+                /// The reserved addresses 0x00 to 0x07 and 0x78 to 0x7F are skipped.
+                pub fn scan(&mut self) -> Scan<'_, $I2CX, PINS> {
+                    Scan { i2c: self, next: FIRST_ADDRESS }
+                }
 
-                    // Wait until the last transmission is finished ???
-                    // busy_wait!(self.i2c, busy);
+                /// Executes `operations` on `addr` as a single transaction
+                ///
+                /// Every operation starts with a (repeated) START and only the last one is
+                /// followed by a STOP, so the master never releases the bus in between.
+                /// Reads after the first operation only send the header of a 10-bit address.
+                pub fn transaction<A>(
+                    &mut self,
+                    addr: A,
+                    operations: &mut [Operation],
+                ) -> Result<(), Error>
+                where
+                    A: Into<Address>,
+                {
+                    let addr = addr.into();
 
-                    // automatic STOP
+                    // A read of no bytes can't be put on the wire, it is skipped
+                    let mut operations = operations
+                        .iter_mut()
+                        .filter(|operation| match operation {
+                            Operation::Read(buffer) => !buffer.is_empty(),
+                            Operation::Write(_) => true,
+                        })
+                        .peekable();
+                    let mut restart = false;
+
+                    while let Some(operation) = operations.next() {
+                        let stop = operations.peek().is_none();
+                        match operation {
+                            Operation::Read(buffer) => {
+                                self.master_read(addr, buffer, restart, stop)?
+                            }
+                            Operation::Write(bytes) => {
+                                self.master_write(addr, bytes, restart, stop)?
+                            }
+                        }
+                        restart = true;
+                    }
 
                     Ok(())
                 }
-            }
-
-            impl<PINS> WriteRead for I2c<$I2CX, PINS> {
-                type Error = Error;
 
-                fn write_read(
+                /// Generates a (repeated) START for a transfer of `total` bytes
+                ///
+                /// The first NBYTES chunk holds `nbytes` of them, RELOAD is set if there
+                /// are more. The PEC byte is only sent or checked before the STOP.
+                fn start(
                     &mut self,
-                    addr: u8,
-                    bytes: &[u8],
-                    buffer: &mut [u8],
+                    addr: Address,
+                    read: bool,
+                    nbytes: usize,
+                    total: usize,
+                    stop: bool,
+                    restart: bool,
                 ) -> Result<(), Error> {
-                    // TODO support transfers of more than 255 bytes
-                    assert!(bytes.len() < 256 && bytes.len() > 0);
-                    assert!(buffer.len() < 256 && buffer.len() > 0);
-
-                    // TODO do we have to explicitly wait here if the bus is busy (e.g. another
-                    // master is communicating)?
+                    let (sadd, add10) = addr.bits()?;
+                    let pec = self.pec && stop;
 
-                    // START and prepare to send `bytes`
                     self.i2c.cr2.write(|w| {
                         w.start()
                             .set_bit()
                             .sadd()
-                            .bits(u16(addr << 1 | 0))
-                            .add10().clear_bit()
+                            .bits(sadd)
+                            .add10().bit(add10)
+                            .head10r().bit(read && restart)
                             .rd_wrn()
-                            .clear_bit()
+                            .bit(read)
                             .nbytes()
-                            .bits(bytes.len() as u8)
+                            .bits(nbytes as u8)
+                            .reload()
+                            .bit(total > nbytes)
                             .autoend()
-                            .clear_bit()
+                            .bit(stop)
+                            .pecbyte()
+                            .bit(pec)
+                    });
 
+                    Ok(())
+                }
+
+                /// Waits for the current NBYTES chunk to be done and loads the next one
+                /// out of `remaining` bytes
+                fn next_chunk(&mut self, remaining: usize) -> Result<(), Error> {
+                    busy_wait!(self, tcr);
+                    self.i2c.cr2.modify(|_, w| {
+                        w.nbytes()
+                            .bits(cmp::min(remaining, MAX_NBYTES) as u8)
+                            .reload()
+                            .bit(remaining > MAX_NBYTES)
                     });
-                    //busy_wait!(self.i2c, addr);
-                    for byte in bytes {
+
+                    Ok(())
+                }
+
+                /// Number of bytes on the wire for `len` data bytes, including the PEC
+                fn total(&self, len: usize, stop: bool) -> usize {
+                    if self.pec && stop {
+                        len + 1
+                    } else {
+                        len
+                    }
+                }
+
+                /// Sends the `len` bytes of `bytes` after a START
+                fn transmit<I>(&mut self, bytes: I, len: usize, stop: bool) -> Result<(), Error>
+                where
+                    I: Iterator<Item = u8>,
+                {
+                    let total = self.total(len, stop);
+
+                    for (i, byte) in bytes.enumerate() {
+                        if i > 0 && i % MAX_NBYTES == 0 {
+                            self.next_chunk(total - i)?;
+                        }
+
                         // Wait until we are allowed to send data (START has been ACKed or last byte
                         // when through)
+                        busy_wait!(self, txis);
+
                         // put byte on the wire
-                        busy_wait!(self.i2c, txis);
-                        self.i2c.txdr.write(|w| w.txdata().bits(*byte));
-                        
+                        self.i2c.txdr.write(|w| w.txdata().bits(byte));
                     }
 
-                    // Wait until the last transmission is finished
-                    busy_wait!(self.i2c, tc);
+                    // The PEC may start a chunk of its own
+                    if total > len && len > 0 && len % MAX_NBYTES == 0 {
+                        self.next_chunk(total - len)?;
+                    }
 
-                    // reSTART and prepare to receive bytes into `buffer`
-                    self.i2c.cr2.write(|w| {
-                        w.sadd()
-                            .bits(u16(addr << 1 | 1))
-                            .add10().clear_bit()
-                            .rd_wrn()
-                            .set_bit()
-                            .nbytes()
-                            .bits(buffer.len() as u8)
-                            .start()
-                            .set_bit()
-                            .autoend()
-                            .set_bit()
-                    });
+                    self.end(stop)
+                }
+
+                /// Receives bytes `done` to `total` of a transfer into `buffer`
+                ///
+                /// If `done` is not zero a chunk ended just before, bytes that don't fit
+                /// into `buffer`, like the PEC, are dropped.
+                fn receive(
+                    &mut self,
+                    buffer: &mut [u8],
+                    done: usize,
+                    total: usize,
+                    stop: bool,
+                ) -> Result<(), Error> {
+                    for i in done..total {
+                        if i > 0 && (i - done) % MAX_NBYTES == 0 {
+                            self.next_chunk(total - i)?;
+                        }
 
-                    for byte in buffer {
                         // Wait until we have received something
-                        busy_wait!(self.i2c, rxne);
+                        busy_wait!(self, rxne);
 
-                        *byte = self.i2c.rxdr.read().rxdata().bits();
+                        let byte = self.i2c.rxdr.read().rxdata().bits();
+                        if let Some(slot) = buffer.get_mut(i - done) {
+                            *slot = byte;
+                        }
                     }
 
-                    // automatic STOP
+                    self.end(stop)
+                }
+
+                /// Waits for the end of a transfer, with a STOP if `stop` is set
+                fn end(&mut self, stop: bool) -> Result<(), Error> {
+                    if stop {
+                        // Wait for the automatic STOP
+                        busy_wait!(self, stopf);
+                        self.i2c.icr.write(|w| w.stopcf().set_bit());
+                    } else {
+                        // Wait until the last transmission is finished
+                        busy_wait!(self, tc);
+                    }
 
                     Ok(())
                 }
+
+                /// Sends `bytes` to `addr`, with a STOP at the end if `stop` is set
+                ///
+                /// Transfers longer than 255 bytes are split into NBYTES sized chunks
+                /// using RELOAD. Without any bytes only the address is sent. Unless
+                /// `restart` is set, which continues a previous transfer, we wait for the
+                /// bus to be free first.
+                fn master_write(
+                    &mut self,
+                    addr: Address,
+                    bytes: &[u8],
+                    restart: bool,
+                    stop: bool,
+                ) -> Result<(), Error> {
+                    if !restart {
+                        self.wait_bus_free()?;
+                    }
+
+                    // START and prepare to send `bytes`
+                    let total = self.total(bytes.len(), stop);
+                    self.start(addr, false, cmp::min(total, MAX_NBYTES), total, stop, restart)?;
+
+                    self.transmit(bytes.iter().cloned(), bytes.len(), stop)
+                }
+
+                /// Receives `buffer` from `addr`, with a STOP at the end if `stop` is set
+                ///
+                /// Transfers longer than 255 bytes are split into NBYTES sized chunks
+                /// using RELOAD. Unless `restart` is set, which continues a previous
+                /// transfer, we wait for the bus to be free first. An empty `buffer` is
+                /// not read, only the STOP that ends a previous transfer is generated.
+                fn master_read(
+                    &mut self,
+                    addr: Address,
+                    buffer: &mut [u8],
+                    restart: bool,
+                    stop: bool,
+                ) -> Result<(), Error> {
+                    if buffer.is_empty() {
+                        if restart && stop {
+                            self.i2c.cr2.modify(|_, w| w.stop().set_bit());
+                            return self.end(true);
+                        }
+
+                        return Ok(());
+                    }
+
+                    if !restart {
+                        self.wait_bus_free()?;
+                    }
+
+                    // (re)START and prepare to receive bytes into `buffer`
+                    let total = self.total(buffer.len(), stop);
+                    self.start(addr, true, cmp::min(total, MAX_NBYTES), total, stop, restart)?;
+
+                    self.receive(buffer, 0, total, stop)
+                }
+            }
+
+            impl<PINS> I2c<$I2CX, PINS> {
+                /// Switches to SMBus host mode
+                ///
+                /// Fails if the kernel clock the timeouts are derived from is not running.
+                pub fn into_smbus(
+                    mut self,
+                    config: SmbusConfig,
+                    clocks: Clocks,
+                ) -> Result<Smbus<$I2CX, PINS>, Error> {
+                    // The timeouts count in steps of 2048 kernel clock cycles, slow kernel
+                    // clocks get the shortest timeout of a single step
+                    let ker_ck_khz = clocks.$ker_ck().ok_or(Error::KernelClock)?.0 / 1_000;
+                    let timeout_a = cmp::max(25 * ker_ck_khz / 2048, 1) - 1;
+                    let timeout_b = cmp::max(10 * ker_ck_khz / 2048, 1) - 1;
+                    assert!(timeout_a < 0x1000);
+                    let (timeout_a, timeout_b) = (timeout_a as u16, timeout_b as u16);
+
+                    self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+
+                    self.i2c.timeoutr.write(|w| {
+                        w.timeouta().bits(timeout_a)
+                            .tidle().clear_bit()
+                            .timouten().bit(config.timeout)
+                            .timeoutb().bits(timeout_b)
+                            .texten().bit(config.timeout)
+                    });
+                    self.i2c.cr1.modify(|_, w| {
+                        w.smbhen().set_bit()
+                            .pecen().bit(config.pec)
+                            .pe().set_bit()
+                    });
+                    self.pec = config.pec;
+
+                    Ok(Smbus { i2c: self, alert: () })
+                }
+            }
+
+            impl<PINS> Smbus<$I2CX, PINS> {
+                /// Enables SMBALERT detection on `pin`
+                pub fn with_alert<ALERT>(self, pin: ALERT) -> Smbus<$I2CX, PINS, ALERT>
+                where
+                    ALERT: SmbAlertPin<$I2CX>,
+                {
+                    self.i2c.i2c.cr1.modify(|_, w| w.alerten().set_bit());
+
+                    Smbus { i2c: self.i2c, alert: pin }
+                }
+            }
+
+            impl<PINS, ALERT> Smbus<$I2CX, PINS, ALERT>
+            where
+                ALERT: SmbAlertPin<$I2CX>,
+            {
+                /// Returns the address of a device that asserted SMBALERT
+                ///
+                /// Returns `WouldBlock` if no alert is pending and `Error::Alert` if no
+                /// device answered the alert response address.
+                pub fn alert_response(&mut self) -> nb::Result<u8, Error> {
+                    if self.i2c.i2c.isr.read().alert().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    self.i2c.i2c.icr.write(|w| w.alertcf().set_bit());
+
+                    let mut address = [0];
+                    match self.read(ALERT_RESPONSE_ADDRESS, &mut address) {
+                        Ok(()) => Ok(address[0] >> 1),
+                        Err(Error::Nack) => Err(nb::Error::Other(Error::Alert)),
+                        Err(e) => Err(nb::Error::Other(e)),
+                    }
+                }
+            }
+
+            impl<PINS, ALERT> Smbus<$I2CX, PINS, ALERT> {
+                /// Sends a command without data
+                pub fn send_byte(&mut self, addr: u8, command: u8) -> Result<(), Error> {
+                    self.write(addr, &[command])
+                }
+
+                /// Receives a byte without sending a command
+                pub fn receive_byte(&mut self, addr: u8) -> Result<u8, Error> {
+                    let mut data = [0];
+                    self.read(addr, &mut data)?;
+
+                    Ok(data[0])
+                }
+
+                /// Writes a byte to `command`
+                pub fn write_byte(&mut self, addr: u8, command: u8, data: u8) -> Result<(), Error> {
+                    self.write(addr, &[command, data])
+                }
+
+                /// Reads a byte from `command`
+                pub fn read_byte(&mut self, addr: u8, command: u8) -> Result<u8, Error> {
+                    let mut data = [0];
+                    self.write_read(addr, &[command], &mut data)?;
+
+                    Ok(data[0])
+                }
+
+                /// Writes a word to `command`, low byte first
+                pub fn write_word(&mut self, addr: u8, command: u8, data: u16) -> Result<(), Error> {
+                    self.write(addr, &[command, data as u8, (data >> 8) as u8])
+                }
+
+                /// Reads a word from `command`, low byte first
+                pub fn read_word(&mut self, addr: u8, command: u8) -> Result<u16, Error> {
+                    let mut data = [0; 2];
+                    self.write_read(addr, &[command], &mut data)?;
+
+                    Ok(u16::from(data[0]) | u16::from(data[1]) << 8)
+                }
+
+                /// Writes `data` to `command` and reads back the word the device answers with
+                pub fn process_call(&mut self, addr: u8, command: u8, data: u16) -> Result<u16, Error> {
+                    let mut answer = [0; 2];
+                    self.write_read(addr, &[command, data as u8, (data >> 8) as u8], &mut answer)?;
+
+                    Ok(u16::from(answer[0]) | u16::from(answer[1]) << 8)
+                }
+
+                /// Writes a block of up to 255 bytes to `command`, preceded by its length
+                pub fn block_write(&mut self, addr: u8, command: u8, data: &[u8]) -> Result<(), Error> {
+                    assert!(data.len() <= MAX_NBYTES);
+
+                    let i2c = &mut self.i2c;
+                    let addr = Address::SevenBit(addr);
+                    let len = data.len() + 2;
+                    let total = i2c.total(len, true);
+
+                    i2c.wait_bus_free()?;
+                    i2c.start(addr, false, cmp::min(total, MAX_NBYTES), total, true, false)?;
+
+                    let bytes = iter::once(command)
+                        .chain(iter::once(data.len() as u8))
+                        .chain(data.iter().cloned());
+                    i2c.transmit(bytes, len, true)
+                }
+
+                /// Reads a block from `command` and returns its length
+                ///
+                /// If the slave sends a byte count of 0 or more bytes than fit into `buffer`,
+                /// the transfer is completed and `Error::BlockLength` is returned.
+                pub fn block_read(&mut self, addr: u8, command: u8, buffer: &mut [u8]) -> Result<usize, Error> {
+                    let i2c = &mut self.i2c;
+                    let addr = Address::SevenBit(addr);
+
+                    i2c.master_write(addr, &[command], false, false)?;
+
+                    // Only the length byte is read at first, it decides how many follow
+                    i2c.start(addr, true, 1, MAX_NBYTES + 1, true, true)?;
+                    busy_wait!(i2c, rxne);
+                    let len = usize::from(i2c.i2c.rxdr.read().rxdata().bits());
+
+                    // The count byte was ACKed, so even after a count of 0 one more byte has
+                    // to be received and NACKed before the STOP
+                    let total = cmp::max(i2c.total(len + 1, true), 2);
+                    let stored = cmp::min(len, buffer.len());
+                    i2c.receive(&mut buffer[..stored], 1, total, true)?;
+
+                    if len == 0 || len > buffer.len() {
+                        return Err(Error::BlockLength);
+                    }
+
+                    Ok(len)
+                }
+
+                /// Releases the I2C peripheral in plain I2C mode and the SMBALERT pin
+                pub fn free(self) -> (I2c<$I2CX, PINS>, ALERT) {
+                    let mut i2c = self.i2c;
+
+                    i2c.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+                    i2c.i2c.timeoutr.reset();
+                    i2c.i2c.cr1.modify(|_, w| {
+                        w.smbhen().clear_bit()
+                            .pecen().clear_bit()
+                            .alerten().clear_bit()
+                            .pe().set_bit()
+                    });
+                    i2c.pec = false;
+
+                    (i2c, self.alert)
+                }
+
+                fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+                    self.i2c.master_write(Address::SevenBit(addr), bytes, false, true)
+                }
+
+                fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Error> {
+                    self.i2c.master_read(Address::SevenBit(addr), buffer, false, true)
+                }
+
+                fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+                    let addr = Address::SevenBit(addr);
+                    self.i2c.master_write(addr, bytes, false, false)?;
+                    self.i2c.master_read(addr, buffer, true, true)
+                }
+            }
+
+            impl<'a, PINS> Iterator for Scan<'a, $I2CX, PINS> {
+                type Item = Result<u8, Error>;
+
+                fn next(&mut self) -> Option<Result<u8, Error>> {
+                    while self.next <= LAST_ADDRESS {
+                        let addr = self.next;
+                        self.next += 1;
+
+                        match self.i2c.probe(addr) {
+                            Ok(true) => return Some(Ok(addr)),
+                            Ok(false) => {}
+                            Err(e) => return Some(Err(e)),
+                        }
+                    }
+
+                    None
+                }
+            }
+
+            impl<PINS> I2c<$I2CX, PINS> {
+                /// Starts a non-blocking transfer that writes `bytes` to `addr` and then
+                /// reads `buffer` from it
+                ///
+                /// Either of them may be empty. The I2C interrupts are enabled, they have to
+                /// be unmasked in the NVIC for the transfer to be driven from there. With an
+                /// address out of range the transfer is done right away with `Error::Address`.
+                pub fn start_transfer<A>(
+                    self,
+                    addr: A,
+                    bytes: &'static [u8],
+                    buffer: &'static mut [u8],
+                ) -> Transfer<$I2CX, PINS>
+                where
+                    A: Into<Address>,
+                {
+                    self.i2c.cr1.modify(|_, w| w.txie().set_bit().rxie().set_bit());
+
+                    <Transfer<$I2CX, PINS>>::new(self, addr.into(), bytes, buffer, ())
+                }
+            }
+
+            impl<PINS, STREAMS> Transfer<$I2CX, PINS, STREAMS> {
+                /// Sets up the transfer and generates the first START
+                fn new(
+                    i2c: I2c<$I2CX, PINS>,
+                    addr: Address,
+                    bytes: &'static [u8],
+                    buffer: &'static mut [u8],
+                    streams: STREAMS,
+                ) -> Self {
+                    let phase = if bytes.is_empty() && !buffer.is_empty() {
+                        Phase::Read
+                    } else {
+                        Phase::Write
+                    };
+
+                    let mut transfer = Transfer {
+                        i2c,
+                        addr,
+                        bytes,
+                        buffer,
+                        streams,
+                        phase,
+                        index: 0,
+                        reloads: 0,
+                        error: None,
+                        result: None,
+                        callback: None,
+                    };
+
+                    transfer.i2c.i2c.cr1.modify(|_, w| {
+                        w.tcie().set_bit()
+                            .stopie().set_bit()
+                            .nackie().set_bit()
+                            .errie().set_bit()
+                    });
+                    transfer.start_segment(false);
+
+                    transfer
+                }
+
+                /// Generates the (repeated) START of the current segment
+                fn start_segment(&mut self, restart: bool) {
+                    let len = self.segment_len();
+                    let read = self.phase == Phase::Read;
+                    let stop = read || self.buffer.is_empty();
+
+                    self.index = 0;
+                    self.reloads = 0;
+                    let start =
+                        self.i2c.start(self.addr, read, cmp::min(len, MAX_NBYTES), len, stop, restart);
+                    if let Err(error) = start {
+                        self.finish(Err(error)).ok();
+                    }
+                }
+
+                /// Handles the pending flags, bytes are only moved if `dma` is not set
+                fn step(&mut self, dma: bool) -> nb::Result<(), Error> {
+                    if let Some(result) = self.result {
+                        return result.map_err(nb::Error::Other);
+                    }
+
+                    let isr = self.i2c.i2c.isr.read();
+
+                    if isr.berr().bit_is_set() {
+                        self.finish(Err(Error::Bus))
+                    } else if isr.arlo().bit_is_set() {
+                        self.finish(Err(Error::Arbitration))
+                    } else if isr.timeout().bit_is_set() {
+                        self.finish(Err(Error::Timeout))
+                    } else if isr.nackf().bit_is_set() {
+                        // A STOP is generated automatically with AUTOEND, otherwise we have to
+                        // request it
+                        self.i2c.i2c.icr.write(|w| w.nackcf().set_bit());
+                        if self.i2c.i2c.cr2.read().autoend().bit_is_clear() {
+                            self.i2c.i2c.cr2.modify(|_, w| w.stop().set_bit());
+                        }
+                        self.error = Some(Error::Nack);
+                        Err(nb::Error::WouldBlock)
+                    } else if !dma && isr.txis().bit_is_set() && self.phase == Phase::Write {
+                        let byte = self.bytes[self.index];
+                        self.i2c.i2c.txdr.write(|w| w.txdata().bits(byte));
+                        self.index += 1;
+                        Err(nb::Error::WouldBlock)
+                    } else if !dma && isr.rxne().bit_is_set() && self.phase == Phase::Read {
+                        self.buffer[self.index] = self.i2c.i2c.rxdr.read().rxdata().bits();
+                        self.index += 1;
+                        Err(nb::Error::WouldBlock)
+                    } else if isr.tcr().bit_is_set() {
+                        self.reloads += 1;
+                        let remaining = self.segment_len() - self.reloads * MAX_NBYTES;
+                        self.i2c.i2c.cr2.modify(|_, w| {
+                            w.nbytes()
+                                .bits(cmp::min(remaining, MAX_NBYTES) as u8)
+                                .reload()
+                                .bit(remaining > MAX_NBYTES)
+                        });
+                        Err(nb::Error::WouldBlock)
+                    } else if isr.tc().bit_is_set() {
+                        // Bytes written, continue with reading the buffer
+                        self.phase = Phase::Read;
+                        self.start_segment(true);
+                        Err(nb::Error::WouldBlock)
+                    } else if isr.stopf().bit_is_set() {
+                        self.i2c.i2c.icr.write(|w| w.stopcf().set_bit());
+                        match self.error.take() {
+                            Some(e) => self.finish(Err(e)),
+                            None => self.finish(Ok(())),
+                        }
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Disables the interrupts and stores the result
+                fn finish(&mut self, result: Result<(), Error>) -> nb::Result<(), Error> {
+                    self.i2c.i2c.cr1.modify(|_, w| {
+                        w.txie().clear_bit()
+                            .rxie().clear_bit()
+                            .tcie().clear_bit()
+                            .stopie().clear_bit()
+                            .nackie().clear_bit()
+                            .errie().clear_bit()
+                            .txdmaen().clear_bit()
+                            .rxdmaen().clear_bit()
+                    });
+                    self.i2c.clear_flags();
+                    self.result = Some(result);
+
+                    result.map_err(nb::Error::Other)
+                }
+            }
+
+            impl<PINS> Transfer<$I2CX, PINS> {
+                /// Advances the transfer, returns its result once it is done
+                pub fn poll(&mut self) -> nb::Result<(), Error> {
+                    let done = self.is_done();
+                    let result = self.step(false);
+                    if !done && self.is_done() {
+                        self.notify();
+                    }
+
+                    result
+                }
+
+                /// Blocks until the transfer is done
+                pub fn wait(&mut self) -> Result<(), Error> {
+                    block!(self.poll())
+                }
+            }
+
+            impl<SCL, SDA> I2cSlave<$I2CX, (SCL, SDA)> {
+                /// Configures the I2C peripheral as a slave
+                ///
+                /// `bus` is either the highest frequency the master uses or a `Config`, it
+                /// determines the data setup and hold times. Fails if the kernel clock
+                /// selected with `CCIPR` is not running or the own address is out of range.
+                pub fn $i2cX<C> (
+                    i2c: $I2CX,
+                    pins: (SCL, SDA),
+                    config: SlaveConfig,
+                    bus: C,
+                    clocks: Clocks,
+                    apb: &mut $APBX
+                ) -> Result<Self, Error> where
+                    C: Into<Config>,
+                    SCL: SclPin<$I2CX>,
+                    SDA: SdaPin<$I2CX>,
+                {
+                    let ker_ck = clocks.$ker_ck().ok_or(Error::KernelClock)?;
+
+                    apb.enr().modify(|_, w| w.$i2cXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$i2cXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$i2cXrst().clear_bit());
+
+                    let bus = bus.into();
+
+                    configure(&i2c, &bus, ker_ck);
+                    set_fast_mode_plus(|w| w.$i2cXfmp().bit(bus.fast_mode_plus));
+
+                    let (oa1, oa1mode) = config.address1.bits()?;
+                    i2c.oar1.write(|w| {
+                        w.oa1().bits(oa1)
+                            .oa1mode().bit(oa1mode)
+                            .oa1en().set_bit()
+                    });
+
+                    if let Some(address2) = config.address2 {
+                        assert!(address2.address < 0x80 && address2.mask < 8);
+                        i2c.oar2.write(|w| {
+                            w.oa2().bits(address2.address)
+                                .oa2msk().bits(address2.mask)
+                                .oa2en().set_bit()
+                        });
+                    }
+
+                    i2c.cr1.modify(|_, w| {
+                        w.gcen().bit(config.general_call)
+                            .nostretch().bit(!config.clock_stretching)
+                            .pe().set_bit()
+                    });
+
+                    Ok(I2cSlave { i2c, pins })
+                }
+
+                /// Releases the I2C peripheral and associated pins
+                ///
+                /// The Fast-mode Plus drive of the pins is switched off again.
+                pub fn free(self) -> ($I2CX, (SCL, SDA)) {
+                    set_fast_mode_plus(|w| w.$i2cXfmp().clear_bit());
+
+                    (self.i2c, self.pins)
+                }
+            }
+
+            impl<PINS> I2cSlave<$I2CX, PINS> {
+                /// Enables the event and error interrupts that `event` reports
+                pub fn listen(&mut self) {
+                    self.i2c.cr1.modify(|_, w| {
+                        w.addrie().set_bit()
+                            .rxie().set_bit()
+                            .txie().set_bit()
+                            .stopie().set_bit()
+                            .nackie().set_bit()
+                            .errie().set_bit()
+                    });
+                }
+
+                /// Disables the event and error interrupts
+                pub fn unlisten(&mut self) {
+                    self.i2c.cr1.modify(|_, w| {
+                        w.addrie().clear_bit()
+                            .rxie().clear_bit()
+                            .txie().clear_bit()
+                            .stopie().clear_bit()
+                            .nackie().clear_bit()
+                            .errie().clear_bit()
+                    });
+                }
+
+                /// Returns the next pending event and clears it
+                ///
+                /// This can be polled or called from the event and error interrupt
+                /// handlers. A `TxRequest` has to be answered with `write_byte`, otherwise
+                /// it is reported again.
+                pub fn event(&mut self) -> nb::Result<SlaveEvent, Error> {
+                    let isr = self.i2c.isr.read();
+
+                    if isr.berr().bit_is_set() {
+                        self.i2c.icr.write(|w| w.berrcf().set_bit());
+                        Err(nb::Error::Other(Error::Bus))
+                    } else if isr.arlo().bit_is_set() {
+                        self.i2c.icr.write(|w| w.arlocf().set_bit());
+                        Err(nb::Error::Other(Error::Arbitration))
+                    } else if isr.ovr().bit_is_set() {
+                        self.i2c.icr.write(|w| w.ovrcf().set_bit());
+                        Err(nb::Error::Other(Error::Overrun))
+                    } else if isr.addr().bit_is_set() {
+                        let direction = if isr.dir().bit_is_set() {
+                            // Drop a byte left over from a previous transfer, so the first
+                            // byte sent is requested with TXIS
+                            self.i2c.isr.write(|w| w.txe().set_bit());
+                            Direction::Read
+                        } else {
+                            Direction::Write
+                        };
+                        let address = isr.addcode().bits();
+
+                        // Clearing ADDR releases SCL
+                        self.i2c.icr.write(|w| w.addrcf().set_bit());
+                        Ok(SlaveEvent::AddressMatched { address, direction })
+                    } else if isr.rxne().bit_is_set() {
+                        Ok(SlaveEvent::RxByte(self.i2c.rxdr.read().rxdata().bits()))
+                    } else if isr.txis().bit_is_set() {
+                        Ok(SlaveEvent::TxRequest)
+                    } else if isr.stopf().bit_is_set() {
+                        self.i2c.icr.write(|w| w.stopcf().set_bit().nackcf().set_bit());
+                        Ok(SlaveEvent::Stop)
+                    } else {
+                        if isr.nackf().bit_is_set() {
+                            // The master does not want any more bytes, a STOP follows
+                            self.i2c.icr.write(|w| w.nackcf().set_bit());
+                        }
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Sends a byte to the master in reply to a `TxRequest`
+                pub fn write_byte(&mut self, byte: u8) {
+                    self.i2c.txdr.write(|w| w.txdata().bits(byte));
+                }
+            }
+
+            impl<PINS> Write for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+                    self.master_write(Address::SevenBit(addr), bytes, false, true)
+                }
+            }
+
+            impl<PINS> WriteRead for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write_read(
+                    &mut self,
+                    addr: u8,
+                    bytes: &[u8],
+                    buffer: &mut [u8],
+                ) -> Result<(), Error> {
+                    let addr = Address::SevenBit(addr);
+                    self.master_write(addr, bytes, false, false)?;
+                    self.master_read(addr, buffer, true, true)
+                }
             }
 
             impl<PINS> Read for I2c<$I2CX, PINS> {
-            type Error = Error;
-
-            fn read(
-                &mut self,
-                addr: u8,
-                buffer: &mut [u8],
-            ) -> Result<(), Error> {
-                // TODO support transfers of more than 255 bytes
-                assert!(buffer.len() < 256 && buffer.len() > 0);
-
-                // TODO do we have to explicitly wait here if the bus is busy (e.g. another
-                // master is communicating)?
-
-                // reSTART and prepare to receive bytes into `buffer`
-                self.i2c.cr2.write(|w| {
-                    w.sadd()
-                        .bits((addr << 1 | 0) as u16)
-                        .rd_wrn()
-                        .set_bit()
-                        .nbytes()
-                        .bits(buffer.len() as u8)
-                        .start()
-                        .set_bit()
-                        .autoend()
-                        .set_bit()
-                });
-
-                for byte in buffer {
-                    // Wait until we have received something
-                    busy_wait!(self.i2c, rxne);
-
-                    *byte = self.i2c.rxdr.read().rxdata().bits();
-                }
-
-                // automatic STOP
-
-                Ok(())
+                type Error = Error;
+
+                fn read(
+                    &mut self,
+                    addr: u8,
+                    buffer: &mut [u8],
+                ) -> Result<(), Error> {
+                    self.master_read(Address::SevenBit(addr), buffer, false, true)
+                }
             }
+        )+
+    };
+}
+
+macro_rules! i2c_dma {
+    ($($I2CX:ident: ($rx_request:expr, $tx_request:expr),)+) => {
+        $(
+            impl<PINS> I2c<$I2CX, PINS> {
+                /// Starts a transfer like `start_transfer`, but with the bytes moved by the
+                /// DMA streams `tx` and `rx`
+                ///
+                /// Only the end of the transfer and errors are handled by the I2C
+                /// interrupts, the buffers have to be accessible by DMA1 and DMA2.
+                pub fn start_transfer_dma<A, TX, RX>(
+                    self,
+                    addr: A,
+                    bytes: &'static [u8],
+                    buffer: &'static mut [u8],
+                    (mut tx, mut rx): (TX, RX),
+                ) -> Transfer<$I2CX, PINS, (TX, RX)>
+                where
+                    A: Into<Address>,
+                    TX: Stream,
+                    RX: Stream,
+                {
+                    assert!(bytes.len() <= usize::from(u16::MAX));
+                    assert!(buffer.len() <= usize::from(u16::MAX));
+
+                    if !bytes.is_empty() {
+                        // NOTE(unsafe) the transfer owns the 'static buffer until the stream
+                        // is disabled
+                        unsafe {
+                            tx.configure(&dma::Config {
+                                request: $tx_request,
+                                direction: dma::Direction::MemoryToPeripheral,
+                                peripheral: &self.i2c.txdr as *const _ as u32,
+                                memory: bytes.as_ptr() as u32,
+                                len: bytes.len() as u16,
+                                size: dma::Size::Byte,
+                                memory_increment: true,
+                                circular: false,
+                                memory1: None,
+                            });
+                        }
+                        tx.enable();
+                        self.i2c.cr1.modify(|_, w| w.txdmaen().set_bit());
+                    }
+
+                    if !buffer.is_empty() {
+                        // NOTE(unsafe) the transfer owns the 'static buffer until the stream
+                        // is disabled
+                        unsafe {
+                            rx.configure(&dma::Config {
+                                request: $rx_request,
+                                direction: dma::Direction::PeripheralToMemory,
+                                peripheral: &self.i2c.rxdr as *const _ as u32,
+                                memory: buffer.as_mut_ptr() as u32,
+                                len: buffer.len() as u16,
+                                size: dma::Size::Byte,
+                                memory_increment: true,
+                                circular: false,
+                                memory1: None,
+                            });
+                        }
+                        rx.enable();
+                        self.i2c.cr1.modify(|_, w| w.rxdmaen().set_bit());
+                    }
+
+                    <Transfer<$I2CX, PINS, (TX, RX)>>::new(self, addr.into(), bytes, buffer, (tx, rx))
+                }
+            }
+
+            impl<PINS, TX, RX> Transfer<$I2CX, PINS, (TX, RX)>
+            where
+                TX: Stream,
+                RX: Stream,
+            {
+                /// Advances the transfer, returns its result once it is done
+                pub fn poll(&mut self) -> nb::Result<(), Error> {
+                    let done = self.is_done();
+                    let result = self.step(true);
+                    if !done && self.is_done() {
+                        // Stop the streams in case the transfer was aborted
+                        if self.streams.0.is_enabled() {
+                            self.streams.0.disable();
+                        }
+                        if self.streams.1.is_enabled() {
+                            self.streams.1.disable();
+                        }
+                        self.notify();
+                    }
+
+                    result
+                }
+
+                /// Blocks until the transfer is done
+                pub fn wait(&mut self) -> Result<(), Error> {
+                    block!(self.poll())
+                }
             }
         )+
     };
 }
 
 i2c!(
-    I2C1: (i2c1, i2c1en, i2c1rst, APB1L, pclk1),
-    I2C2: (i2c2, i2c2en, i2c2rst, APB1L, pclk4),
-    I2C3: (i2c3, i2c3en, i2c3rst, APB1L, pclk4),
-    I2C4: (i2c4, i2c4en, i2c4rst, APB4, pclk4),
+    I2C1: (i2c1, i2c1en, i2c1rst, APB1L, i2c123_ker_ck, i2c1fmp),
+    I2C2: (i2c2, i2c2en, i2c2rst, APB1L, i2c123_ker_ck, i2c2fmp),
+    I2C3: (i2c3, i2c3en, i2c3rst, APB1L, i2c123_ker_ck, i2c3fmp),
+    I2C4: (i2c4, i2c4en, i2c4rst, APB4, i2c4_ker_ck, i2c4fmp),
+);
+
+// DMAMUX1 request lines, I2C4 is only connected to the BDMA
+i2c_dma!(
+    I2C1: (33, 34),
+    I2C2: (35, 36),
+    I2C3: (73, 74),
 );
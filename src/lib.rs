@@ -7,14 +7,18 @@ extern crate nb;
 extern crate void;
 
 pub mod gpio;
+pub mod dma;
 pub mod flash;
 pub mod i2c;
+pub mod lin;
 pub mod rcc;
 pub mod time;
 pub mod delay;
 pub mod watchdog;
 pub mod prelude;
 pub mod serial;
+pub mod spi;
+pub mod bus;
 pub use stm32h7::stm32h7x3;
 pub use stm32h7::stm32h7x3 as pac;
 pub use compile_time_calculations::*;
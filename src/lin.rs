@@ -0,0 +1,387 @@
+//! Local Interconnect Network (LIN) implementation
+//!
+//! This builds on the `Tx` and `Rx` halves of a `Serial` that has been switched into LIN mode
+//! with `Serial::enable_lin`. As a LIN transceiver echoes everything that is sent on the bus,
+//! every byte written is read back and compared, which also works for both master and slave
+//! nodes on the same wire.
+
+use cortex_m::peripheral::DWT;
+use hal::serial::{Read, Write};
+use nb::block;
+use void::{self, Void};
+use crate::serial;
+use crate::time::{Bps, Instant, MilliSeconds, MonoTimer};
+
+/// The sync byte following the break of every header
+const SYNC: u8 = 0x55;
+
+/// Largest payload of a LIN frame
+pub const MAX_DATA_LEN: usize = 8;
+
+/// LIN error
+#[derive(Debug)]
+pub enum Error {
+    /// Error of the underlying USART
+    Serial(serial::Error),
+    /// The byte following the break was not the sync byte
+    Sync,
+    /// The parity bits of the protected identifier are wrong
+    Parity,
+    /// The checksum of a response did not match
+    Checksum,
+    /// A byte read back from the bus differs from the byte that was sent
+    Bit,
+    /// The bus did not deliver the expected bytes in time
+    Timeout,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Checksum model of a frame
+#[derive(Clone, Copy, PartialEq)]
+pub enum Checksum {
+    /// LIN 1.x checksum over the data bytes only
+    Classic,
+    /// LIN 2.x checksum over the protected identifier and the data bytes
+    Enhanced,
+}
+
+/// Transmitter that can send a LIN break
+pub trait SendBreak {
+    /// Requests a break to be sent before the next data byte
+    fn send_break(&mut self) -> nb::Result<(), Void>;
+}
+
+/// Receiver that can detect a LIN break
+pub trait DetectBreak {
+    /// Waits for a break to be detected and clears it
+    fn read_break(&mut self) -> nb::Result<(), Void>;
+}
+
+/// Computes the protected identifier of a 6 bit frame identifier
+pub fn protected_id(id: u8) -> u8 {
+    let id = id & 0x3f;
+    let bit = |n: u8| (id >> n) & 1;
+
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+
+    id | (p0 << 6) | (p1 << 7)
+}
+
+/// Computes the checksum of a response
+///
+/// The diagnostic frames 0x3C and 0x3D always use the classic checksum.
+pub fn checksum(pid: u8, data: &[u8], model: Checksum) -> u8 {
+    let id = pid & 0x3f;
+    let mut sum: u16 = if model == Checksum::Enhanced && id != 0x3c && id != 0x3d {
+        u16::from(pid)
+    } else {
+        0
+    };
+
+    for byte in data {
+        sum += u16::from(*byte);
+        if sum > 0xff {
+            sum -= 0xff;
+        }
+    }
+
+    !(sum as u8)
+}
+
+/// LIN node
+pub struct Lin<TX, RX> {
+    tx: TX,
+    rx: RX,
+    timer: MonoTimer,
+    bit_ticks: u32,
+}
+
+impl<TX, RX> Lin<TX, RX>
+where
+    TX: Write<u8, Error = Void> + SendBreak,
+    RX: Read<u8, Error = serial::Error> + DetectBreak,
+{
+    /// Creates a LIN node from the halves of a `Serial` in LIN mode
+    ///
+    /// `baud_rate` has to match the baud rate the `Serial` was configured with, it is used
+    /// together with `timer` to compute the frame timeouts.
+    pub fn new(tx: TX, rx: RX, baud_rate: Bps, timer: MonoTimer) -> Self {
+        let bit_ticks = timer.frequency().0 / baud_rate.0;
+
+        Lin {
+            tx,
+            rx,
+            timer,
+            bit_ticks,
+        }
+    }
+
+    /// Sends a header for frame `id` as the master node
+    pub fn send_header(&mut self, id: u8) -> Result<(), Error> {
+        let deadline = (self.timer.now(), self.header_timeout());
+        self.send_header_until(id, deadline)
+    }
+
+    /// Sends a header, giving up once the deadline has passed
+    fn send_header_until(&mut self, id: u8, deadline: (Instant, u32)) -> Result<(), Error> {
+        let tx = &mut self.tx;
+        poll_until(deadline, || tx.send_break())?;
+        // The break is echoed back as well, wait until it is over before sending sync
+        let rx = &mut self.rx;
+        poll_until(deadline, || rx.read_break())?;
+
+        self.write_checked(SYNC, deadline)?;
+        self.write_checked(protected_id(id), deadline)
+    }
+
+    /// Waits for a header as a slave node and returns its frame identifier
+    ///
+    /// This blocks until a break is detected on the bus.
+    pub fn read_header(&mut self) -> Result<u8, Error> {
+        block!(self.rx.read_break()).ok();
+
+        let deadline = (self.timer.now(), self.sync_timeout());
+        if self.read_byte(deadline)? != SYNC {
+            return Err(Error::Sync);
+        }
+
+        let pid = self.read_byte(deadline)?;
+        if protected_id(pid) != pid {
+            return Err(Error::Parity);
+        }
+
+        Ok(pid & 0x3f)
+    }
+
+    /// Publishes the response for frame `id` after its header was sent
+    pub fn write_response(&mut self, id: u8, data: &[u8], model: Checksum) -> Result<(), Error> {
+        let deadline = (self.timer.now(), self.response_timeout(data.len()));
+        self.write_response_until(id, data, model, deadline)
+    }
+
+    /// Publishes a response, giving up once the deadline has passed
+    fn write_response_until(
+        &mut self,
+        id: u8,
+        data: &[u8],
+        model: Checksum,
+        deadline: (Instant, u32),
+    ) -> Result<(), Error> {
+        assert!(!data.is_empty() && data.len() <= MAX_DATA_LEN);
+
+        for byte in data {
+            self.write_checked(*byte, deadline)?;
+        }
+
+        self.write_checked(checksum(protected_id(id), data, model), deadline)
+    }
+
+    /// Receives the response for frame `id` after its header was sent
+    pub fn read_response(&mut self, id: u8, buffer: &mut [u8], model: Checksum) -> Result<(), Error> {
+        let deadline = (self.timer.now(), self.response_timeout(buffer.len()));
+        self.read_response_until(id, buffer, model, deadline)
+    }
+
+    /// Receives a response, giving up once the deadline has passed
+    fn read_response_until(
+        &mut self,
+        id: u8,
+        buffer: &mut [u8],
+        model: Checksum,
+        deadline: (Instant, u32),
+    ) -> Result<(), Error> {
+        assert!(!buffer.is_empty() && buffer.len() <= MAX_DATA_LEN);
+
+        for byte in buffer.iter_mut() {
+            *byte = self.read_byte(deadline)?;
+        }
+
+        if self.read_byte(deadline)? != checksum(protected_id(id), buffer, model) {
+            return Err(Error::Checksum);
+        }
+
+        Ok(())
+    }
+
+    /// Sends a complete frame as the master node, header and response
+    ///
+    /// The whole frame has to complete within its maximum duration.
+    pub fn write_frame(&mut self, id: u8, data: &[u8], model: Checksum) -> Result<(), Error> {
+        let deadline = (self.timer.now(), self.frame_timeout(data.len()));
+        self.send_header_until(id, deadline)?;
+        self.write_response_until(id, data, model, deadline)
+    }
+
+    /// Requests a complete frame from a slave node as the master node
+    ///
+    /// The whole frame has to complete within its maximum duration.
+    pub fn read_frame(&mut self, id: u8, buffer: &mut [u8], model: Checksum) -> Result<(), Error> {
+        let deadline = (self.timer.now(), self.frame_timeout(buffer.len()));
+        self.send_header_until(id, deadline)?;
+        self.read_response_until(id, buffer, model, deadline)
+    }
+
+    /// Releases the transmitter and receiver
+    pub fn free(self) -> (TX, RX) {
+        (self.tx, self.rx)
+    }
+
+    /// Maximum header duration: 1.4 times the nominal 34 bits, including the break
+    fn header_timeout(&self) -> u32 {
+        self.bit_ticks * 48
+    }
+
+    /// Maximum header duration after the break: 1.4 times the nominal 20 bits
+    fn sync_timeout(&self) -> u32 {
+        self.bit_ticks * 28
+    }
+
+    /// Maximum response duration: 1.4 times the nominal 10 bits per byte plus checksum
+    fn response_timeout(&self, len: usize) -> u32 {
+        self.bit_ticks * 14 * (len as u32 + 1)
+    }
+
+    /// Maximum frame duration: the header plus the response
+    fn frame_timeout(&self, len: usize) -> u32 {
+        self.header_timeout() + self.response_timeout(len)
+    }
+
+    /// Reads a byte from the bus, giving up once `timeout` ticks have passed since `start`
+    ///
+    /// The deadline is shared by all bytes of a header or response, so the frame as a whole
+    /// has to complete in time.
+    fn read_byte(&mut self, (start, timeout): (Instant, u32)) -> Result<u8, Error> {
+        loop {
+            match self.rx.read() {
+                Ok(byte) => return Ok(byte),
+                Err(nb::Error::Other(e)) => return Err(Error::Serial(e)),
+                Err(nb::Error::WouldBlock) => {
+                    if start.elapsed() > timeout {
+                        return Err(Error::Timeout);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes a byte to the bus and verifies its echo
+    fn write_checked(&mut self, byte: u8, deadline: (Instant, u32)) -> Result<(), Error> {
+        block!(self.tx.write(byte)).ok();
+
+        if self.read_byte(deadline)? != byte {
+            return Err(Error::Bit);
+        }
+
+        Ok(())
+    }
+}
+
+/// Polls `f` until it succeeds, giving up once `timeout` ticks have passed since `start`
+fn poll_until<F>((start, timeout): (Instant, u32), mut f: F) -> Result<(), Error>
+where
+    F: FnMut() -> nb::Result<(), Void>,
+{
+    loop {
+        match f() {
+            Ok(()) => return Ok(()),
+            Err(nb::Error::Other(e)) => void::unreachable(e),
+            Err(nb::Error::WouldBlock) => {
+                if start.elapsed() > timeout {
+                    return Err(Error::Timeout);
+                }
+            }
+        }
+    }
+}
+
+/// Direction of the response of a scheduled frame, seen from the master
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// The master publishes the response
+    Publish,
+    /// A slave publishes the response
+    Subscribe,
+}
+
+/// Entry of a master schedule table
+#[derive(Clone, Copy)]
+pub struct Slot {
+    /// Frame identifier
+    pub id: u8,
+    /// Who publishes the response
+    pub direction: Direction,
+    /// Number of data bytes of the response, from 1 to `MAX_DATA_LEN`
+    pub len: u8,
+    /// Checksum model of the frame
+    pub checksum: Checksum,
+    /// Duration of the slot, the next frame starts after it
+    pub duration: MilliSeconds,
+}
+
+/// Master schedule table, cycling through its slots
+pub struct Schedule<'a> {
+    slots: &'a [Slot],
+    index: usize,
+}
+
+impl<'a> Schedule<'a> {
+    /// Creates a schedule table starting at its first slot
+    ///
+    /// Panics if there are no slots or if the length of a slot is out of range.
+    pub fn new(slots: &'a [Slot]) -> Self {
+        assert!(!slots.is_empty());
+        assert!(slots
+            .iter()
+            .all(|slot| (1..=MAX_DATA_LEN).contains(&usize::from(slot.len))));
+
+        Schedule { slots, index: 0 }
+    }
+
+    /// Processes the current slot and waits until it is over
+    ///
+    /// For published frames `f` is called with the slot and a buffer of `len` bytes to fill
+    /// before the response is sent, for subscribed frames it is called with the received
+    /// response. The schedule advances to the next slot even if the frame failed.
+    pub fn run_slot<TX, RX, F>(&mut self, lin: &mut Lin<TX, RX>, mut f: F) -> Result<(), Error>
+    where
+        TX: Write<u8, Error = Void> + SendBreak,
+        RX: Read<u8, Error = serial::Error> + DetectBreak,
+        F: FnMut(&Slot, &mut [u8]),
+    {
+        let slot = self.slots[self.index];
+        self.index = (self.index + 1) % self.slots.len();
+
+        let start = lin.timer.now();
+        let ticks = u64::from(slot.duration.0) * u64::from(lin.timer.frequency().0 / 1_000);
+
+        let mut buffer = [0; MAX_DATA_LEN];
+        let buffer = &mut buffer[..usize::from(slot.len)];
+        let result = match slot.direction {
+            Direction::Publish => {
+                f(&slot, buffer);
+                lin.write_frame(slot.id, buffer, slot.checksum)
+            }
+            Direction::Subscribe => lin
+                .read_frame(slot.id, buffer, slot.checksum)
+                .map(|_| f(&slot, buffer)),
+        };
+
+        // The cycle counter wraps after a few seconds, so the elapsed ticks are summed up
+        let mut elapsed = u64::from(start.elapsed());
+        let mut last = DWT::get_cycle_count();
+        while elapsed < ticks {
+            let now = DWT::get_cycle_count();
+            elapsed += u64::from(now.wrapping_sub(last));
+            last = now;
+        }
+
+        result
+    }
+
+    /// Restarts the schedule at its first slot
+    pub fn reset(&mut self) {
+        self.index = 0;
+    }
+}
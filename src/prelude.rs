@@ -1,5 +1,6 @@
 pub use crate::rcc::RccExt;
 pub use crate::gpio::GpioExt;
+pub use crate::dma::DmaExt;
 pub use crate::flash::FlashExt;
 pub use crate::time::U32Ext;
 pub use crate::hal::digital::InputPin as _embedded_hal_digital_InputPin;
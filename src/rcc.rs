@@ -1,6 +1,6 @@
 //! Reset and clock control implementation
 
-use stm32h7::stm32h7x3::{rcc, RCC};
+use stm32h7::stm32h7x3::{rcc, PWR, RCC};
 use cast::{u8, u16};
 use crate::time::Hertz;
 use crate::flash::ACR;
@@ -23,6 +23,7 @@ impl RccExt for RCC {
             apb2: APB2 { _0: ()},
             apb3: APB3 { _0: ()},
             apb4: APB4 { _0: ()},
+            ccipr: CCIPR { _0: ()},
             cfgr: CFGR {
                 hclk1: None,
                 hclk2: None,
@@ -61,6 +62,8 @@ pub struct Rcc {
     pub apb3: APB3,
     /// Advanced Peripheral Bus 4 (APB1) registers
     pub apb4: APB4,
+    /// Kernel clock configuration registers
+    pub ccipr: CCIPR,
     /// Clock configuration
     pub cfgr: CFGR,
 }
@@ -128,7 +131,433 @@ apb!(
     APB4: (apb4, APB4ENR, apb4enr, APB4RSTR, apb4rstr),
 );
 
-const HSI: u32 = 64_000_000; // Hz
+pub(crate) const HSI: u32 = 64_000_000; // Hz
+pub(crate) const CSI: u32 = 4_000_000; // Hz
+pub(crate) const LSE: u32 = 32_768; // Hz
+
+/// Enables the clock of SYSCFG, which is shared by several drivers
+pub(crate) fn enable_syscfg() {
+    // NOTE(unsafe) read-modify-write within a critical section, setting the bit is idempotent
+    cortex_m::interrupt::free(|_| unsafe {
+        (*RCC::ptr()).apb4enr.modify(|_, w| w.syscfgen().set_bit())
+    });
+}
+
+/// Output divider of a PLL
+#[derive(Clone, Copy)]
+pub(crate) enum PllOutput {
+    P,
+    Q,
+    R,
+}
+
+/// Returns the frequency of an output of PLL1, PLL2 or PLL3 as configured in the registers
+///
+/// Returns `None` if the PLL or the output is stopped, or if the PLL runs from the HSE, whose
+/// frequency is not known here.
+pub(crate) fn pll_ck(pll: u8, output: PllOutput) -> Option<Hertz> {
+    assert!((1..=3).contains(&pll));
+
+    // NOTE(unsafe) atomic reads with no side effects
+    let rcc = unsafe { &*RCC::ptr() };
+    let index = u32::from(pll - 1);
+    let (output, shift) = match output {
+        PllOutput::P => (0, 9),
+        PllOutput::Q => (1, 16),
+        PllOutput::R => (2, 24),
+    };
+
+    let cr = rcc.cr.read().bits();
+    let cfgr = rcc.pllcfgr.read().bits();
+    // PLLxRDY and DIVxyEN
+    if cr & (1 << (25 + 2 * index)) == 0 || cfgr & (1 << (16 + 3 * index + output)) == 0 {
+        return None;
+    }
+
+    let ckselr = rcc.pllckselr.read().bits();
+    let ref_ck = match ckselr & 0b11 {
+        0b00 => HSI >> ((cr >> 3) & 0b11),
+        0b01 => CSI,
+        _ => return None,
+    };
+    let divm = (ckselr >> (4 + 8 * index)) & 0x3f;
+    if divm == 0 {
+        return None;
+    }
+
+    let (divr, fracr) = match pll {
+        1 => (rcc.pll1divr.read().bits(), rcc.pll1fracr.read().bits()),
+        2 => (rcc.pll2divr.read().bits(), rcc.pll2fracr.read().bits()),
+        _ => (rcc.pll3divr.read().bits(), rcc.pll3fracr.read().bits()),
+    };
+    let divn = u64::from(divr & 0x1ff) + 1;
+    let div = u64::from((divr >> shift) & 0x7f) + 1;
+    let fracn = if cfgr & (1 << (4 * index)) != 0 {
+        u64::from((fracr >> 3) & 0x1fff)
+    } else {
+        0
+    };
+
+    let vco = u64::from(ref_ck) * (divn * 8192 + fracn) / (u64::from(divm) * 8192);
+    Some(Hertz((vco / div) as u32))
+}
+
+/// A simple struct providing safe APIs to the kernel clock configuration registers
+pub struct CCIPR {
+    _0: (),
+}
+
+impl CCIPR {
+    /// Provides access to the d1ccipr register
+    pub(crate) fn d1ccipr(&mut self) -> &rcc::D1CCIPR {
+        unsafe {&(*RCC::ptr()).d1ccipr}
+    }
+
+    /// Provides access to the d2ccip1r register
+    pub(crate) fn d2ccip1r(&mut self) -> &rcc::D2CCIP1R {
+        unsafe {&(*RCC::ptr()).d2ccip1r}
+    }
+
+    /// Provides access to the d2ccip2r register
+    pub(crate) fn d2ccip2r(&mut self) -> &rcc::D2CCIP2R {
+        unsafe {&(*RCC::ptr()).d2ccip2r}
+    }
+
+    /// Provides access to the d3ccipr register
+    pub(crate) fn d3ccipr(&mut self) -> &rcc::D3CCIPR {
+        unsafe {&(*RCC::ptr()).d3ccipr}
+    }
+
+    /// Turns on the oscillator a kernel clock is derived from and keeps it running in Stop mode
+    fn enable_oscillator(&mut self, oscillator: Option<Oscillator>) {
+        let rcc = unsafe { &*RCC::ptr()};
+
+        match oscillator {
+            Some(Oscillator::Hsi) => {
+                rcc.cr.modify(|_, w| w.hsion().set_bit().hsikeron().set_bit());
+                while rcc.cr.read().hsirdy().bit_is_clear() {}
+            },
+            Some(Oscillator::Csi) => {
+                rcc.cr.modify(|_, w| w.csion().set_bit().csikeron().set_bit());
+                while rcc.cr.read().csirdy().bit_is_clear() {}
+            },
+            None => {},
+        }
+    }
+
+    /// Turns on the LSE, bypassed by an external clock on OSC32_IN if `bypass` is set
+    ///
+    /// Returns `false` and switches it off again if it does not become ready within its
+    /// startup time of 2 s, e.g. because no crystal is fitted. Every poll takes at least one
+    /// cycle of `sys_ck`, which bounds the wait.
+    fn enable_lse(&mut self, bypass: bool, sys_ck: Hertz) -> bool {
+        let rcc = unsafe { &*RCC::ptr()};
+        if rcc.bdcr.read().lserdy().bit_is_set() {
+            return true;
+        }
+
+        // NOTE(unsafe) the LSE is part of the backup domain, which has to be unlocked
+        let pwr = unsafe { &*PWR::ptr() };
+        pwr.pwr_cr1.modify(|_, w| w.dbp().set_bit());
+        while pwr.pwr_cr1.read().dbp().bit_is_clear() {}
+
+        // LSEBYP may only be changed while the LSE is off
+        rcc.bdcr.modify(|_, w| w.lseon().clear_bit());
+        rcc.bdcr.modify(|_, w| w.lsebyp().bit(bypass));
+        rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+
+        let ready = (0..2 * sys_ck.0).any(|_| rcc.bdcr.read().lserdy().bit_is_set());
+        if !ready {
+            rcc.bdcr.modify(|_, w| w.lseon().clear_bit());
+        }
+        ready
+    }
+
+    /// Turns on the oscillator a USART kernel clock is derived from
+    ///
+    /// Returns `false` if the LSE failed to start, see `enable_lse`.
+    pub(crate) fn enable_usart_clock(&mut self, clock: UsartClock, sys_ck: Hertz) -> bool {
+        match clock {
+            UsartClock::Lse => self.enable_lse(false, sys_ck),
+            UsartClock::LseBypass => self.enable_lse(true, sys_ck),
+            clock => {
+                self.enable_oscillator(clock.oscillator());
+                true
+            }
+        }
+    }
+
+    /// Selects the kernel clock of USART1 and USART6, enabled with `enable_usart_clock`
+    pub(crate) fn usart16(&mut self, clock: UsartClock) {
+        self.d2ccip2r().modify(|_, w| unsafe { w.usart16src().bits(clock.bits()) });
+    }
+
+    /// Selects the kernel clock of USART2, USART3, UART4, UART5, UART7 and UART8, enabled
+    /// with `enable_usart_clock`
+    pub(crate) fn usart234578(&mut self, clock: UsartClock) {
+        self.d2ccip2r().modify(|_, w| unsafe { w.usart234578src().bits(clock.bits()) });
+    }
+
+    /// Selects the kernel clock of I2C1, I2C2 and I2C3
+    ///
+    /// This has to happen before the I2C peripherals are created, their timing is derived
+    /// from it.
+    pub fn i2c123(&mut self, clock: I2cClock) {
+        self.enable_oscillator(clock.oscillator());
+        self.d2ccip2r().modify(|_, w| unsafe { w.i2c123src().bits(clock.bits()) });
+    }
+
+    /// Selects the kernel clock of I2C4
+    ///
+    /// This has to happen before the I2C peripheral is created, its timing is derived
+    /// from it.
+    pub fn i2c4(&mut self, clock: I2cClock) {
+        self.enable_oscillator(clock.oscillator());
+        self.d3ccipr().modify(|_, w| unsafe { w.i2c4src().bits(clock.bits()) });
+    }
+
+    /// Selects the source of per_ck
+    ///
+    /// per_ck is shared by every peripheral that selects it as its kernel clock, so this
+    /// has to happen before any of them is created.
+    pub fn per_ck(&mut self, clock: PerClock) {
+        self.enable_oscillator(Some(clock.oscillator()));
+        self.d1ccipr().modify(|_, w| unsafe { w.ckpersrc().bits(clock.bits()) });
+    }
+
+    /// Selects the kernel clock of SPI1, SPI2 and SPI3
+    ///
+    /// This has to happen before the SPI peripherals are created, their baud rate is
+    /// derived from it. `freeze` enables none of the PLL outputs, the source of per_ck is
+    /// selected with `per_ck`.
+    pub fn spi123(&mut self, clock: Spi123Clock) {
+        self.d2ccip1r().modify(|_, w| unsafe { w.spi123src().bits(clock.bits()) });
+    }
+
+    /// Selects the kernel clock of SPI4 and SPI5
+    ///
+    /// This has to happen before the SPI peripherals are created, their baud rate is
+    /// derived from it.
+    pub fn spi45(&mut self, clock: SpiClock) {
+        self.enable_oscillator(clock.oscillator());
+        self.d2ccip1r().modify(|_, w| unsafe { w.spi45src().bits(clock.bits()) });
+    }
+
+    /// Selects the kernel clock of SPI6
+    ///
+    /// This has to happen before the SPI peripheral is created, its baud rate is derived
+    /// from it.
+    pub fn spi6(&mut self, clock: SpiClock) {
+        self.enable_oscillator(clock.oscillator());
+        self.d3ccipr().modify(|_, w| unsafe { w.spi6src().bits(clock.bits()) });
+    }
+}
+
+/// Oscillators a kernel clock can be derived from
+#[derive(Clone, Copy)]
+enum Oscillator {
+    Hsi,
+    Csi,
+}
+
+/// Kernel clock source of a USART
+#[derive(Clone, Copy, PartialEq)]
+pub enum UsartClock {
+    /// The APB clock of the USART, it is stopped in Stop mode
+    Pclk,
+    /// The 64 MHz HSI oscillator
+    Hsi,
+    /// The 4 MHz CSI oscillator
+    Csi,
+    /// The 32.768 kHz LSE oscillator, it is switched on when selected
+    Lse,
+    /// A 32.768 kHz clock applied to OSC32_IN, bypassing the LSE oscillator
+    LseBypass,
+}
+
+impl UsartClock {
+    /// Returns the value of the USARTxSRC bits selecting this clock
+    fn bits(&self) -> u8 {
+        match self {
+            UsartClock::Pclk => 0b000,
+            UsartClock::Hsi => 0b011,
+            UsartClock::Csi => 0b100,
+            UsartClock::Lse | UsartClock::LseBypass => 0b101,
+        }
+    }
+
+    /// Returns the frequency of this clock, `pclk` being the APB clock of the USART
+    pub(crate) fn frequency(&self, pclk: Hertz) -> Hertz {
+        match self {
+            UsartClock::Pclk => pclk,
+            UsartClock::Hsi => Hertz(HSI),
+            UsartClock::Csi => Hertz(CSI),
+            UsartClock::Lse | UsartClock::LseBypass => Hertz(LSE),
+        }
+    }
+
+    /// Returns the oscillator this clock is derived from
+    fn oscillator(&self) -> Option<Oscillator> {
+        match self {
+            UsartClock::Hsi => Some(Oscillator::Hsi),
+            UsartClock::Csi => Some(Oscillator::Csi),
+            UsartClock::Pclk | UsartClock::Lse | UsartClock::LseBypass => None,
+        }
+    }
+}
+
+/// Kernel clock source of an I2C peripheral
+#[derive(Clone, Copy, PartialEq)]
+pub enum I2cClock {
+    /// The APB clock of the I2C peripheral, it is stopped in Stop mode
+    Pclk,
+    /// The 64 MHz HSI oscillator
+    Hsi,
+    /// The 4 MHz CSI oscillator
+    Csi,
+}
+
+impl I2cClock {
+    /// Returns the value of the I2CxSRC bits selecting this clock
+    fn bits(&self) -> u8 {
+        match self {
+            I2cClock::Pclk => 0b00,
+            I2cClock::Hsi => 0b10,
+            I2cClock::Csi => 0b11,
+        }
+    }
+
+    /// Returns the frequency selected by the I2CxSRC bits, `pclk` being the APB clock of the
+    /// I2C peripheral
+    ///
+    /// `pll3_r_ck` can only be selected by writing the register directly, `None` is returned
+    /// if it is stopped or derived from the HSE.
+    fn frequency(bits: u8, pclk: Hertz) -> Option<Hertz> {
+        match bits {
+            0b00 => Some(pclk),
+            0b01 => pll_ck(3, PllOutput::R),
+            0b10 => Some(Hertz(HSI)),
+            _ => Some(Hertz(CSI)),
+        }
+    }
+
+    /// Returns the oscillator this clock is derived from
+    fn oscillator(&self) -> Option<Oscillator> {
+        match self {
+            I2cClock::Hsi => Some(Oscillator::Hsi),
+            I2cClock::Csi => Some(Oscillator::Csi),
+            I2cClock::Pclk => None,
+        }
+    }
+}
+
+/// Source of per_ck, the common kernel clock of several peripherals
+#[derive(Clone, Copy, PartialEq)]
+pub enum PerClock {
+    /// The 64 MHz HSI oscillator
+    Hsi,
+    /// The 4 MHz CSI oscillator
+    Csi,
+}
+
+impl PerClock {
+    /// Returns the value of the CKPERSRC bits selecting this clock
+    fn bits(&self) -> u8 {
+        match self {
+            PerClock::Hsi => 0b00,
+            PerClock::Csi => 0b01,
+        }
+    }
+
+    /// Returns the frequency of per_ck selected by the CKPERSRC bits, `None` for the HSE
+    fn frequency(ckpersrc: u8) -> Option<Hertz> {
+        match ckpersrc {
+            0b00 => Some(Hertz(HSI)),
+            0b01 => Some(Hertz(CSI)),
+            _ => None,
+        }
+    }
+
+    /// Returns the oscillator this clock is derived from
+    fn oscillator(&self) -> Oscillator {
+        match self {
+            PerClock::Hsi => Oscillator::Hsi,
+            PerClock::Csi => Oscillator::Csi,
+        }
+    }
+}
+
+/// Kernel clock source of SPI1, SPI2 and SPI3
+#[derive(Clone, Copy, PartialEq)]
+pub enum Spi123Clock {
+    /// Output Q of PLL1, selected after reset
+    Pll1Q,
+    /// Output P of PLL2
+    Pll2P,
+    /// Output P of PLL3
+    Pll3P,
+    /// per_ck, its source is selected with `CCIPR::per_ck`
+    PerCk,
+}
+
+impl Spi123Clock {
+    /// Returns the value of the SPI123SRC bits selecting this clock
+    fn bits(&self) -> u8 {
+        match self {
+            Spi123Clock::Pll1Q => 0b000,
+            Spi123Clock::Pll2P => 0b001,
+            Spi123Clock::Pll3P => 0b010,
+            Spi123Clock::PerCk => 0b100,
+        }
+    }
+}
+
+/// Kernel clock source of SPI4, SPI5 and SPI6
+#[derive(Clone, Copy, PartialEq)]
+pub enum SpiClock {
+    /// The APB clock of the SPI peripheral, it is stopped in Stop mode
+    Pclk,
+    /// The 64 MHz HSI oscillator
+    Hsi,
+    /// The 4 MHz CSI oscillator
+    Csi,
+}
+
+impl SpiClock {
+    /// Returns the value of the SPIxSRC bits selecting this clock
+    fn bits(&self) -> u8 {
+        match self {
+            SpiClock::Pclk => 0b000,
+            SpiClock::Hsi => 0b011,
+            SpiClock::Csi => 0b100,
+        }
+    }
+
+    /// Returns the frequency selected by the SPIxSRC bits, `pclk` being the APB clock of the
+    /// SPI peripheral
+    ///
+    /// The PLL outputs can only be selected by writing the register directly, `None` is
+    /// returned if they are stopped and for the HSE.
+    fn frequency(bits: u8, pclk: Hertz) -> Option<Hertz> {
+        match bits {
+            0b000 => Some(pclk),
+            0b001 => pll_ck(2, PllOutput::Q),
+            0b010 => pll_ck(3, PllOutput::Q),
+            0b011 => Some(Hertz(HSI)),
+            0b100 => Some(Hertz(CSI)),
+            _ => None,
+        }
+    }
+
+    /// Returns the oscillator this clock is derived from
+    fn oscillator(&self) -> Option<Oscillator> {
+        match self {
+            SpiClock::Hsi => Some(Oscillator::Hsi),
+            SpiClock::Csi => Some(Oscillator::Csi),
+            SpiClock::Pclk => None,
+        }
+    }
+}
 
 /// Clock configuration
 pub struct CFGR {
@@ -537,4 +966,43 @@ impl Clocks {
     pub fn d3ppre(&self) -> u8 {
         self.d3ppre
     }
+    /// The kernel clock frequency of I2C1, I2C2 and I2C3, `None` if it is stopped or unknown
+    pub(crate) fn i2c123_ker_ck(&self) -> Option<Hertz> {
+        // NOTE(unsafe) atomic read with no side effects
+        let bits = unsafe { (*RCC::ptr()).d2ccip2r.read().i2c123src().bits() };
+        I2cClock::frequency(bits, self.pclk1)
+    }
+    /// The kernel clock frequency of I2C4, `None` if it is stopped or unknown
+    pub(crate) fn i2c4_ker_ck(&self) -> Option<Hertz> {
+        // NOTE(unsafe) atomic read with no side effects
+        let bits = unsafe { (*RCC::ptr()).d3ccipr.read().i2c4src().bits() };
+        I2cClock::frequency(bits, self.pclk4)
+    }
+    /// The kernel clock frequency of SPI1, SPI2 and SPI3, `None` if it is stopped or unknown
+    ///
+    /// After reset pll1_q_ck is selected, which `freeze` does not enable.
+    pub(crate) fn spi123_ker_ck(&self) -> Option<Hertz> {
+        // NOTE(unsafe) atomic reads with no side effects
+        let rcc = unsafe { &*RCC::ptr() };
+        match rcc.d2ccip1r.read().spi123src().bits() {
+            0b000 => pll_ck(1, PllOutput::Q),
+            0b001 => pll_ck(2, PllOutput::P),
+            0b010 => pll_ck(3, PllOutput::P),
+            0b100 => PerClock::frequency(rcc.d1ccipr.read().ckpersrc().bits()),
+            // I2S_CKIN is an external clock
+            _ => None,
+        }
+    }
+    /// The kernel clock frequency of SPI4 and SPI5, `None` if it is stopped or unknown
+    pub(crate) fn spi45_ker_ck(&self) -> Option<Hertz> {
+        // NOTE(unsafe) atomic read with no side effects
+        let bits = unsafe { (*RCC::ptr()).d2ccip1r.read().spi45src().bits() };
+        SpiClock::frequency(bits, self.pclk2)
+    }
+    /// The kernel clock frequency of SPI6, `None` if it is stopped or unknown
+    pub(crate) fn spi6_ker_ck(&self) -> Option<Hertz> {
+        // NOTE(unsafe) atomic read with no side effects
+        let bits = unsafe { (*RCC::ptr()).d3ccipr.read().spi6src().bits() };
+        SpiClock::frequency(bits, self.pclk4)
+    }
 }
\ No newline at end of file
@@ -2,17 +2,20 @@ use core::marker::PhantomData;
 use core::ptr;
 
 use hal::serial;
+use hal::spi::{FullDuplex, Mode, Phase, Polarity};
 use nb;
 use void::Void;
-use crate::gpio::{Floating, Input, Output, PushPull, AF4, AF7};
-use crate::gpio::gpioa::{PA2, PA3, PA9, PA10};
-use crate::gpio::gpiob::{PB6, PB7, PB10, PB11, PB14, PB15};
-use crate::gpio::gpioc::{PC6, PC10, PC11};
-use crate::gpio::gpiod::{PD5, PD6, PD8, PD9};
-use crate::gpio::gpiog::{PG9, PG14};
-use crate::stm32h7x3::{USART1, USART2, USART3, USART6};
-use crate::time::Bps;
-use crate::rcc::{APB1L, APB2, Clocks};
+use crate::gpio::{Alternate, Locked, OpenDrain, AF4, AF7};
+use crate::gpio::gpioa::{PA2, PA3, PA4, PA8, PA9, PA10};
+use crate::gpio::gpiob::{PB6, PB7, PB10, PB11, PB12, PB14, PB15};
+use crate::gpio::gpioc::{PC6, PC8, PC10, PC11, PC12};
+use crate::gpio::gpiod::{PD5, PD6, PD7, PD8, PD9, PD10};
+#[cfg(feature = "gpio-fg")]
+use crate::gpio::gpiog::{PG7, PG9, PG14};
+use crate::stm32h7x3::{usart1, USART1, USART2, USART3, USART6};
+use crate::lin;
+use crate::time::{Bps, Hertz, MilliSeconds, MonoTimer};
+use crate::rcc::{APB1L, APB2, CCIPR, Clocks, UsartClock};
 
 /// Interrupt event
 pub enum Event {
@@ -20,6 +23,71 @@ pub enum Event {
     Rxne,
     /// New data can be sent
     Txe,
+    /// A LIN break has been detected
+    LinBreak,
+    /// The USART woke up the device from Stop mode
+    Wakeup,
+}
+
+/// Event waking up the device from Stop mode
+pub enum WakeupEvent {
+    /// A frame with the address of the USART has been received
+    AddressMatch,
+    /// A start bit has been detected
+    StartBit,
+    /// A complete frame has been received
+    Rxne,
+}
+
+/// Number of data bits of a frame, including the parity bit if enabled
+pub enum WordLength {
+    /// 7 data bits
+    DataBits7,
+    /// 8 data bits
+    DataBits8,
+    /// 9 data bits, use `Read<u16>` and `Write<u16>` to access the ninth bit
+    DataBits9,
+}
+
+/// Length of the node address compared in address mark mute mode
+pub enum AddressLength {
+    /// The address is compared against the 4 LSBs of the received address frame
+    Bits4,
+    /// The address is compared against the 7 LSBs of the received address frame
+    Bits7,
+}
+
+/// How the receiver leaves mute mode
+pub enum MuteWakeup {
+    /// An idle frame wakes up the receiver
+    IdleLine,
+    /// An address frame with the MSB set and a matching address wakes up the receiver
+    AddressMark {
+        /// Address of this node
+        address: u8,
+        /// Number of address bits
+        length: AddressLength,
+    },
+}
+
+/// Auto baud rate detection method, selecting what the first received character is expected to be
+pub enum AutoBaudMode {
+    /// Any character starting with a 1 bit, the start bit is measured
+    StartBit,
+    /// Any character starting with a 10xx bit pattern, the falling edges are measured
+    FallingEdge,
+    /// A 0x7F frame
+    Frame7F,
+    /// A 0x55 frame
+    Frame55,
+}
+
+/// Length of a break that is detected as a LIN break
+pub enum LinBreakLength {
+    /// 10 bit break detection
+    Bits10,
+    /// 11 bit break detection
+    Bits11,
 }
 
 /// Serial error
@@ -33,6 +101,14 @@ pub enum Error {
     Overrun,
     /// Parity check error
     Parity,
+    /// Auto baud rate detection failed
+    AutoBaudRate,
+    /// The baud rate can't be derived from the kernel clock
+    BaudRate,
+    /// The expected event did not happen in time
+    Timeout,
+    /// The oscillator of the kernel clock did not start, e.g. no LSE crystal is fitted
+    KernelClock,
     #[doc(hidden)]
     _Extensible,
 }
@@ -43,61 +119,263 @@ pub unsafe trait TxPin<USART> {}
 /// RX pin - DO NOT IMPLEMENT THIS TRAIT
 pub unsafe trait RxPin<USART> {}
 
+/// CK pin - DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait CkPin<USART> {}
+
+/// Open drain TX pin used as the only data line - DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait SingleWirePin<USART> {}
+
+unsafe impl<USART, PIN: TxPin<USART>> TxPin<USART> for Locked<PIN> {}
+unsafe impl<USART, PIN: RxPin<USART>> RxPin<USART> for Locked<PIN> {}
+unsafe impl<USART, PIN: CkPin<USART>> CkPin<USART> for Locked<PIN> {}
+unsafe impl<USART, PIN: SingleWirePin<USART>> SingleWirePin<USART> for Locked<PIN> {}
+
 // USART1 TX
-unsafe impl TxPin<USART1> for PB14<Output<PushPull>, AF4> {}
-unsafe impl TxPin<USART1> for PA9<Output<PushPull>, AF7> {}
-unsafe impl TxPin<USART1> for PB6<Output<PushPull>, AF7> {}
+unsafe impl TxPin<USART1> for PB14<Alternate<AF4>> {}
+unsafe impl TxPin<USART1> for PA9<Alternate<AF7>> {}
+unsafe impl TxPin<USART1> for PB6<Alternate<AF7>> {}
 
 // USART2 TX
-unsafe impl TxPin<USART2> for PA2<Output<PushPull>, AF7> {}
-unsafe impl TxPin<USART2> for PD5<Output<PushPull>, AF7> {}
+unsafe impl TxPin<USART2> for PA2<Alternate<AF7>> {}
+unsafe impl TxPin<USART2> for PD5<Alternate<AF7>> {}
 
 // USART3 TX
-unsafe impl TxPin<USART3> for PB10<Output<PushPull>, AF7> {}
-unsafe impl TxPin<USART3> for PD8<Output<PushPull>, AF7> {}
-unsafe impl TxPin<USART3> for PC10<Output<PushPull>, AF7> {}
+unsafe impl TxPin<USART3> for PB10<Alternate<AF7>> {}
+unsafe impl TxPin<USART3> for PD8<Alternate<AF7>> {}
+unsafe impl TxPin<USART3> for PC10<Alternate<AF7>> {}
 
 // USART6 TX
-unsafe impl TxPin<USART6> for PC6<Output<PushPull>, AF7> {}
-unsafe impl TxPin<USART6> for PG14<Output<PushPull>, AF7> {}
+unsafe impl TxPin<USART6> for PC6<Alternate<AF7>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl TxPin<USART6> for PG14<Alternate<AF7>> {}
 
 // USART1 RX
-unsafe impl RxPin<USART1> for PB15<Input<Floating>, AF4> {}
-unsafe impl RxPin<USART1> for PA10<Input<Floating>, AF7> {}
-unsafe impl RxPin<USART1> for PB7<Input<Floating>, AF7> {}
+unsafe impl RxPin<USART1> for PB15<Alternate<AF4>> {}
+unsafe impl RxPin<USART1> for PA10<Alternate<AF7>> {}
+unsafe impl RxPin<USART1> for PB7<Alternate<AF7>> {}
 
 // USART2 RX
-unsafe impl RxPin<USART2> for PA3<Input<Floating>, AF7> {}
-unsafe impl RxPin<USART2> for PD6<Input<Floating>, AF7> {}
+unsafe impl RxPin<USART2> for PA3<Alternate<AF7>> {}
+unsafe impl RxPin<USART2> for PD6<Alternate<AF7>> {}
 
 // USART3 RX
-unsafe impl RxPin<USART3> for PB11<Input<Floating>, AF7> {}
-unsafe impl RxPin<USART3> for PD9<Input<Floating>, AF7> {}
-unsafe impl RxPin<USART3> for PC11<Input<Floating>, AF7> {}
+unsafe impl RxPin<USART3> for PB11<Alternate<AF7>> {}
+unsafe impl RxPin<USART3> for PD9<Alternate<AF7>> {}
+unsafe impl RxPin<USART3> for PC11<Alternate<AF7>> {}
 
 // USART6 RX
-unsafe impl RxPin<USART6> for PC6<Input<Floating>, AF7> {}
-unsafe impl RxPin<USART6> for PG9<Input<Floating>, AF7> {}
+unsafe impl RxPin<USART6> for PC6<Alternate<AF7>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl RxPin<USART6> for PG9<Alternate<AF7>> {}
+
+// USART1 CK
+unsafe impl CkPin<USART1> for PA8<Alternate<AF7>> {}
+
+// USART2 CK
+unsafe impl CkPin<USART2> for PA4<Alternate<AF7>> {}
+unsafe impl CkPin<USART2> for PD7<Alternate<AF7>> {}
+
+// USART3 CK
+unsafe impl CkPin<USART3> for PB12<Alternate<AF7>> {}
+unsafe impl CkPin<USART3> for PC12<Alternate<AF7>> {}
+unsafe impl CkPin<USART3> for PD10<Alternate<AF7>> {}
+
+// USART6 CK
+unsafe impl CkPin<USART6> for PC8<Alternate<AF7>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl CkPin<USART6> for PG7<Alternate<AF7>> {}
+
+// USART1 single wire
+unsafe impl SingleWirePin<USART1> for PB14<Alternate<AF4, OpenDrain>> {}
+unsafe impl SingleWirePin<USART1> for PA9<Alternate<AF7, OpenDrain>> {}
+unsafe impl SingleWirePin<USART1> for PB6<Alternate<AF7, OpenDrain>> {}
+
+// USART2 single wire
+unsafe impl SingleWirePin<USART2> for PA2<Alternate<AF7, OpenDrain>> {}
+unsafe impl SingleWirePin<USART2> for PD5<Alternate<AF7, OpenDrain>> {}
+
+// USART3 single wire
+unsafe impl SingleWirePin<USART3> for PB10<Alternate<AF7, OpenDrain>> {}
+unsafe impl SingleWirePin<USART3> for PD8<Alternate<AF7, OpenDrain>> {}
+unsafe impl SingleWirePin<USART3> for PC10<Alternate<AF7, OpenDrain>> {}
+
+// USART6 single wire
+unsafe impl SingleWirePin<USART6> for PC6<Alternate<AF7, OpenDrain>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl SingleWirePin<USART6> for PG14<Alternate<AF7, OpenDrain>> {}
+
+/// Full-duplex asynchronous mode (type state)
+pub struct Asynchronous;
+
+/// Single-wire half-duplex mode (type state)
+pub struct HalfDuplex;
+
+/// Smartcard (ISO 7816) mode (type state)
+pub struct SmartCard;
+
+/// IrDA SIR mode (type state)
+pub struct IrDA;
+
+/// Smartcard mode configuration
+pub struct SmartCardConfig {
+    /// Frequency of the clock provided to the card on CK
+    pub clock: Hertz,
+    /// Guard time in baud clock cycles, TC is only set after it elapsed
+    pub guard_time: u8,
+    /// Send a NACK on parity errors while receiving
+    pub nack: bool,
+    /// Number of retransmissions after a NACK before reporting a framing error, 0 to 7
+    pub retries: u8,
+}
+
+/// IrDA SIR power mode
+pub enum IrDAPower {
+    /// Normal mode, pulses are 3/16 of the bit period
+    Normal,
+    /// Low-power mode, pulses are 3 periods of a clock close to 1.8432 MHz
+    LowPower,
+}
 
 /// Serial abstraction
-pub struct Serial<USART, PINS> {
+pub struct Serial<USART, PINS, MODE = Asynchronous> {
     usart: USART,
     pins: PINS,
+    ker_ck: Hertz,
+    _mode: PhantomData<MODE>,
 }
 
 /// Serial receiver
-pub struct Rx<USART> {
+///
+/// It implements `Read` for both `u8` and `u16` frames, so the frame type has to be known
+/// when reading, e.g. `let byte: u8 = block!(rx.read())?`.
+pub struct Rx<USART, MODE = Asynchronous> {
     _usart: PhantomData<USART>,
+    _mode: PhantomData<MODE>,
 }
 
 /// Serial transmitter
-pub struct Tx<USART> {
+///
+/// It implements `Write` for both `u8` and `u16` frames, so methods that don't take a frame
+/// have to name the implementation, e.g. `Write::<u8>::flush(&mut tx)`.
+pub struct Tx<USART, MODE = Asynchronous> {
     _usart: PhantomData<USART>,
+    _mode: PhantomData<MODE>,
+}
+
+/// USART in synchronous master mode, acting as an SPI master
+///
+/// The pins are `(CK, MISO, MOSI)`, MISO being the RX and MOSI the TX pin of the USART.
+/// Data is shifted out MSB first, like on a regular SPI bus.
+pub struct UsartSpi<USART, PINS> {
+    usart: USART,
+    pins: PINS,
+}
+
+/// Returns the baud rate the USART runs at with the kernel clock `ker_ck`
+fn baud_rate(usart: &usart1::RegisterBlock, ker_ck: Hertz) -> u32 {
+    let brr = usart.brr.read().bits();
+    if usart.cr1.read().over8().bit_is_set() {
+        // BRR[2:0] holds USARTDIV[3:0] shifted right by one
+        let usartdiv = (brr & !0xf) | ((brr & 0x7) << 1);
+        2 * ker_ck.0 / usartdiv
+    } else {
+        ker_ck.0 / brr
+    }
+}
+
+/// Returns OVER8 and the BRR value for `baud_rate`, oversampling by 8 only if the kernel
+/// clock is too slow to oversample by 16
+fn baud_rate_divider(ker_ck: Hertz, baud_rate: u32) -> Result<(bool, u32), Error> {
+    let usartdiv = ker_ck.0 / baud_rate;
+    if usartdiv >= 16 {
+        return Ok((false, usartdiv));
+    }
+
+    let usartdiv = 2 * ker_ck.0 / baud_rate;
+    if usartdiv >= 16 {
+        Ok((true, (usartdiv & !0xf) | ((usartdiv & 0xf) >> 1)))
+    } else {
+        Err(Error::BaudRate)
+    }
+}
+
+/// Implements `serial::Write` for a mode that can't fail during transmission
+macro_rules! tx_void {
+    ($USARTX:ident, $MODE:ident) => {
+        impl serial::Write<u8> for Tx<$USARTX, $MODE> {
+            // NOTE(Void) See section "29.7 USART interrupts"; the only possible errors during
+            // transmission are: clear to send (which is disabled in this case) errors and
+            // framing errors (which only occur in SmartCard mode); neither of these apply to
+            // the modes this is implemented for
+            type Error = Void;
+
+            fn flush(&mut self) -> nb::Result<(), Void> {
+                // NOTE(unsafe) atomic read with no side effects
+                let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+
+                if isr.tc().bit_is_set() {
+                    Ok(())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+
+            fn write(&mut self, byte: u8) -> nb::Result<(), Void> {
+                // NOTE(unsafe) atomic read with no side effects
+                let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+
+                if isr.txe().bit_is_set() {
+                    // NOTE(unsafe) atomic write to stateless register
+                    // NOTE(write_volatile) 8-bit write that's not possible through the svd2rust API
+                    unsafe {
+                        ptr::write_volatile(ptr::addr_of!((*$USARTX::ptr()).tdr) as *mut u8, byte)
+                    }
+
+                    // NOTE(point 8) we maybe gotta implement point 8 on page 2031 of the reference manual here if we encounter bugs
+                    Ok(())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+        }
+
+        impl serial::Write<u16> for Tx<$USARTX, $MODE> {
+            // NOTE(Void) see above
+            type Error = Void;
+
+            fn flush(&mut self) -> nb::Result<(), Void> {
+                serial::Write::<u8>::flush(self)
+            }
+
+            fn write(&mut self, word: u16) -> nb::Result<(), Void> {
+                // NOTE(unsafe) atomic read with no side effects
+                let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+
+                if isr.txe().bit_is_set() {
+                    // NOTE(unsafe) atomic write to stateless register
+                    unsafe { (*$USARTX::ptr()).tdr.write(|w| w.tdr().bits(word & 0x1ff)) }
+                    Ok(())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+        }
+    };
 }
 
 macro_rules! hal {
     ($(
-        $USARTX:ident: ($usartX:ident, $APB:ident, $usartXen:ident, $usartXrst:ident, $pclkX:ident),
+        $USARTX:ident: (
+            $usartX:ident,
+            $usartX_half_duplex:ident,
+            $usartX_smartcard:ident,
+            $usartX_irda:ident,
+            $APB:ident,
+            $usartXen:ident,
+            $usartXrst:ident,
+            $pclkX:ident,
+            $usartXsrc:ident
+        ),
     )+) => {
         $(
             impl<TX, RX> Serial<$USARTX, (TX, RX)> {
@@ -136,9 +414,273 @@ macro_rules! hal {
                         .cr1
                         .write(|w| w.ue().set_bit().re().set_bit().te().set_bit());
 
-                    Serial { usart, pins }
+                    Serial { usart, pins, ker_ck: clocks.$pclkX(), _mode: PhantomData }
                 }
+            }
+
+            impl<TX> Serial<$USARTX, TX, HalfDuplex> {
+                /// Configures a USART peripheral for single-wire half-duplex communication
+                ///
+                /// TX and RX share the open drain TX pin, which needs an external or internal
+                /// pull up
+                pub fn $usartX_half_duplex(
+                    usart: $USARTX,
+                    pin: TX,
+                    baud_rate: Bps,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    TX: SingleWirePin<$USARTX>,
+                {
+                    // enable or reset $USARTX
+                    apb.enr().modify(|_, w| w.$usartXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$usartXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$usartXrst().clear_bit());
+
+                    // Word length = 8 bit
+                    usart.cr1.write(|w|  w.m0().clear_bit().m1().clear_bit());
 
+                    // Configure the baud rate
+                    let brr = clocks.$pclkX().0 / baud_rate.0;
+                    assert!(brr >= 16, "impossible baud rate");
+                    usart.brr.write(|w| unsafe { w.bits(brr) });
+
+                    // Set stop bits to 1, LINEN and CLKEN have to be cleared in half-duplex mode
+                    usart.cr2.write(|w| w.stop().bits(0b00));
+
+                    // HDSEL: connect RX internally to the TX pin
+                    usart.cr3.write(|w| w.hdsel().set_bit());
+
+                    // UE: enable USART
+                    // RE: enable receiver
+                    // TE: enable transceiver
+                    usart
+                        .cr1
+                        .write(|w| w.ue().set_bit().re().set_bit().te().set_bit());
+
+                    Serial { usart, pins: pin, ker_ck: clocks.$pclkX(), _mode: PhantomData }
+                }
+            }
+
+            impl<TX, CK> Serial<$USARTX, (TX, CK), SmartCard> {
+                /// Configures a USART peripheral to communicate with an ISO 7816 smartcard
+                ///
+                /// The open drain TX pin is the bidirectional I/O line of the card, CK provides
+                /// its clock. Frames are 8 data bits with even parity and 1.5 stop bits.
+                pub fn $usartX_smartcard(
+                    usart: $USARTX,
+                    pins: (TX, CK),
+                    baud_rate: Bps,
+                    config: SmartCardConfig,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    TX: SingleWirePin<$USARTX>,
+                    CK: CkPin<$USARTX>,
+                {
+                    assert!(config.retries < 8, "at most 7 retries are possible");
+
+                    // enable or reset $USARTX
+                    apb.enr().modify(|_, w| w.$usartXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$usartXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$usartXrst().clear_bit());
+
+                    // Word length = 9 bit including the parity bit, even parity
+                    usart.cr1.write(|w| {
+                        w.m0()
+                            .set_bit()
+                            .m1()
+                            .clear_bit()
+                            .pce()
+                            .set_bit()
+                            .ps()
+                            .clear_bit()
+                    });
+
+                    // Configure the baud rate
+                    let brr = clocks.$pclkX().0 / baud_rate.0;
+                    assert!(brr >= 16, "impossible baud rate");
+                    usart.brr.write(|w| unsafe { w.bits(brr) });
+
+                    // The card clock is the kernel clock divided by 2 * PSC
+                    let psc = clocks.$pclkX().0 / (2 * config.clock.0);
+                    assert!(psc > 0 && psc < 32, "impossible smartcard clock");
+                    usart.gtpr.write(|w| w.psc().bits(psc as u8).gt().bits(config.guard_time));
+
+                    // Set stop bits to 1.5 and output the card clock on CK
+                    usart.cr2.write(|w| w.stop().bits(0b11).clken().set_bit());
+
+                    // SCEN: enable smartcard mode
+                    // NACK: request a retransmission on parity errors
+                    // SCARCNT: retransmissions before reporting a framing error
+                    usart.cr3.write(|w| {
+                        w.scen()
+                            .set_bit()
+                            .nack()
+                            .bit(config.nack)
+                            .scarcnt()
+                            .bits(config.retries)
+                    });
+
+                    // UE: enable USART
+                    // RE: enable receiver
+                    // TE: enable transceiver
+                    usart.cr1.modify(|_, w| w.ue().set_bit().re().set_bit().te().set_bit());
+
+                    Serial { usart, pins, ker_ck: clocks.$pclkX(), _mode: PhantomData }
+                }
+            }
+
+            impl<TX, RX> Serial<$USARTX, (TX, RX), IrDA> {
+                /// Configures a USART peripheral to drive an IrDA SIR transceiver
+                pub fn $usartX_irda(
+                    usart: $USARTX,
+                    pins: (TX, RX),
+                    baud_rate: Bps,
+                    power: IrDAPower,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    TX: TxPin<$USARTX>,
+                    RX: RxPin<$USARTX>,
+                {
+                    // IrDA SIR is limited to 115200 bps
+                    assert!(baud_rate.0 <= 115_200, "impossible baud rate");
+
+                    // enable or reset $USARTX
+                    apb.enr().modify(|_, w| w.$usartXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$usartXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$usartXrst().clear_bit());
+
+                    // Word length = 8 bit
+                    usart.cr1.write(|w|  w.m0().clear_bit().m1().clear_bit());
+
+                    // Configure the baud rate
+                    let brr = clocks.$pclkX().0 / baud_rate.0;
+                    assert!(brr >= 16, "impossible baud rate");
+                    usart.brr.write(|w| unsafe { w.bits(brr) });
+
+                    // PSC has to be 1 in normal mode, in low-power mode it divides the kernel
+                    // clock down to the low-power frequency
+                    let psc = match power {
+                        IrDAPower::Normal => 1,
+                        IrDAPower::LowPower => {
+                            let psc = clocks.$pclkX().0 / 1_843_200;
+                            assert!(psc > 0 && psc < 256, "impossible low-power frequency");
+                            psc as u8
+                        }
+                    };
+                    usart.gtpr.write(|w| w.psc().bits(psc));
+
+                    // Set stop bits to 1, LINEN, STOP and CLKEN have to be cleared in IrDA mode
+                    usart.cr2.write(|w| w.stop().bits(0b00));
+
+                    // IREN: enable IrDA mode
+                    // IRLP: select low-power mode
+                    usart.cr3.write(|w| {
+                        w.iren().set_bit().irlp().bit(match power {
+                            IrDAPower::Normal => false,
+                            IrDAPower::LowPower => true,
+                        })
+                    });
+
+                    // UE: enable USART
+                    // RE: enable receiver
+                    // TE: enable transceiver
+                    usart
+                        .cr1
+                        .write(|w| w.ue().set_bit().re().set_bit().te().set_bit());
+
+                    Serial { usart, pins, ker_ck: clocks.$pclkX(), _mode: PhantomData }
+                }
+            }
+
+            impl<PINS> Serial<$USARTX, PINS> {
+                /// Detects the baud rate of the next received character and switches to it
+                ///
+                /// This blocks until the character has been received, it stays readable
+                /// afterwards. If no character arrives within `timeout`, measured with
+                /// `timer`, `Error::Timeout` is returned and the baud rate stays unchanged.
+                pub fn autobaud(
+                    &mut self,
+                    mode: AutoBaudMode,
+                    timer: MonoTimer,
+                    timeout: MilliSeconds,
+                ) -> Result<Bps, Error> {
+                    // ABREN and ABRMOD may only be written while the USART is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+
+                    self.usart.cr2.modify(|_, w| {
+                        w.abren().set_bit().abrmod().bits(match mode {
+                            AutoBaudMode::StartBit => 0b00,
+                            AutoBaudMode::FallingEdge => 0b01,
+                            AutoBaudMode::Frame7F => 0b10,
+                            AutoBaudMode::Frame55 => 0b11,
+                        })
+                    });
+
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+
+                    // ABRRQ: restart the detection in case it already ran before
+                    self.usart.rqr.write(|w| w.abrrq().set_bit());
+
+                    let ticks = timeout.0 * (timer.frequency().0 / 1_000);
+                    let start = timer.now();
+                    let result = loop {
+                        let isr = self.usart.isr.read();
+                        if isr.abre().bit_is_set() {
+                            break Err(Error::AutoBaudRate);
+                        } else if isr.abrf().bit_is_set() {
+                            break Ok(Bps(baud_rate(&self.usart, self.ker_ck)));
+                        } else if start.elapsed() > ticks {
+                            break Err(Error::Timeout);
+                        }
+                    };
+
+                    if result.is_err() {
+                        // Stop the detection, so it can't change the baud rate later on
+                        self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                        self.usart.cr2.modify(|_, w| w.abren().clear_bit());
+                        self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                    }
+
+                    result
+                }
+
+                /// Switches the USART into LIN mode
+                ///
+                /// Use the `lin` module on the halves returned by `split` to talk LIN
+                pub fn enable_lin(&mut self, break_length: LinBreakLength) {
+                    // LINEN may only be written while the USART is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+
+                    // LIN mode requires 1 stop bit and no clock, smartcard,
+                    // half-duplex or IrDA mode
+                    self.usart.cr2.modify(|_, w| {
+                        w.stop()
+                            .bits(0b00)
+                            .clken()
+                            .clear_bit()
+                            .linen()
+                            .set_bit()
+                            .lbdl()
+                            .bit(match break_length {
+                                LinBreakLength::Bits10 => false,
+                                LinBreakLength::Bits11 => true,
+                            })
+                    });
+                    self.usart.cr3.modify(|_, w| {
+                        w.scen().clear_bit().hdsel().clear_bit().iren().clear_bit()
+                    });
+
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+            }
+
+            impl<PINS, MODE> Serial<$USARTX, PINS, MODE> {
                 /// Starts listening for an interrupt event
                 pub fn listen(&mut self, event: Event) {
                     match event {
@@ -148,6 +690,12 @@ macro_rules! hal {
                         Event::Txe => {
                             self.usart.cr1.modify(|_, w| w.txeie().set_bit())
                         },
+                        Event::LinBreak => {
+                            self.usart.cr2.modify(|_, w| w.lbdie().set_bit())
+                        },
+                        Event::Wakeup => {
+                            self.usart.cr3.modify(|_, w| w.wufie().set_bit())
+                        },
                     }
                 }
 
@@ -160,28 +708,287 @@ macro_rules! hal {
                         Event::Txe => {
                             self.usart.cr1.modify(|_, w| w.txeie().clear_bit())
                         },
+                        Event::LinBreak => {
+                            self.usart.cr2.modify(|_, w| w.lbdie().clear_bit())
+                        },
+                        Event::Wakeup => {
+                            self.usart.cr3.modify(|_, w| w.wufie().clear_bit())
+                        },
                     }
                 }
 
+                /// Switches the kernel clock of the USART, keeping the current baud rate
+                ///
+                /// Only the HSI, CSI and LSE keep running in Stop mode, one of them has to be
+                /// selected to wake up the device. The USART oversamples by 8 if the clock is
+                /// too slow to oversample by 16, if it is too slow for that as well, e.g. the
+                /// LSE above 4096 Bd, `Error::BaudRate` is returned and nothing is changed.
+                /// `Error::KernelClock` is returned if the LSE does not start.
+                pub fn set_kernel_clock(
+                    &mut self,
+                    clock: UsartClock,
+                    clocks: Clocks,
+                    ccipr: &mut CCIPR,
+                ) -> Result<(), Error> {
+                    let ker_ck = clock.frequency(clocks.$pclkX());
+                    let (over8, brr) =
+                        baud_rate_divider(ker_ck, baud_rate(&self.usart, self.ker_ck))?;
+                    if !ccipr.enable_usart_clock(clock, clocks.sys_ck()) {
+                        return Err(Error::KernelClock);
+                    }
+
+                    // The kernel clock and OVER8 may only be switched while the USART is
+                    // disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+
+                    ccipr.$usartXsrc(clock);
+                    self.ker_ck = ker_ck;
+
+                    self.usart.brr.write(|w| unsafe { w.bits(brr) });
+                    self.usart.cr1.modify(|_, w| w.over8().bit(over8).ue().set_bit());
+
+                    Ok(())
+                }
+
+                /// Enables waking up the device from Stop mode on `event`
+                ///
+                /// The kernel clock has to be switched to a clock running in Stop mode with
+                /// `set_kernel_clock` beforehand. Listen to `Event::Wakeup` and unmask the
+                /// USART wakeup line in the EXTI to get an interrupt.
+                pub fn enable_wakeup(&mut self, event: WakeupEvent) {
+                    // WUS may only be written while the USART is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+
+                    self.usart.cr3.modify(|_, w| unsafe {
+                        w.wus().bits(match event {
+                            WakeupEvent::AddressMatch => 0b00,
+                            WakeupEvent::StartBit => 0b10,
+                            WakeupEvent::Rxne => 0b11,
+                        })
+                    });
+
+                    // UESM: the USART may request its kernel clock in Stop mode
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit().uesm().set_bit());
+                }
+
+                /// Disables waking up the device from Stop mode
+                pub fn disable_wakeup(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.uesm().clear_bit());
+                }
+
+                /// Clears the wakeup flag after the device woke up
+                pub fn clear_wakeup(&mut self) {
+                    self.usart.icr.write(|w| w.wucf().set_bit());
+                }
+
+                /// Sets the number of data bits per frame
+                pub fn set_word_length(&mut self, length: WordLength) {
+                    // M0 and M1 may only be written while the USART is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+
+                    let (m1, m0) = match length {
+                        WordLength::DataBits7 => (true, false),
+                        WordLength::DataBits8 => (false, false),
+                        WordLength::DataBits9 => (false, true),
+                    };
+                    self.usart.cr1.modify(|_, w| w.m1().bit(m1).m0().bit(m0));
+
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Enables mute mode for multiprocessor communication
+                ///
+                /// The receiver drops all frames while muted, it is muted with `mute` and leaves
+                /// mute mode as configured by `wakeup`. In address mark mode the MSB of a frame
+                /// marks an address frame, so 9 data bits are usually used.
+                pub fn enable_mute_mode(&mut self, wakeup: MuteWakeup) {
+                    // WAKE, ADD and ADDM7 may only be written while the USART is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+
+                    match wakeup {
+                        MuteWakeup::IdleLine => {
+                            self.usart.cr1.modify(|_, w| w.wake().clear_bit());
+                        },
+                        MuteWakeup::AddressMark { address, length } => {
+                            let (address, addm7) = match length {
+                                AddressLength::Bits4 => {
+                                    assert!(address < 0x10, "address exceeds 4 bits");
+                                    (address, false)
+                                },
+                                AddressLength::Bits7 => {
+                                    assert!(address < 0x80, "address exceeds 7 bits");
+                                    (address, true)
+                                },
+                            };
+                            self.usart.cr2.modify(|_, w| w.add().bits(address).addm7().bit(addm7));
+                            self.usart.cr1.modify(|_, w| w.wake().set_bit());
+                        },
+                    }
+
+                    // MME: allow the receiver to enter mute mode
+                    self.usart.cr1.modify(|_, w| w.mme().set_bit().ue().set_bit());
+                }
+
+                /// Disables mute mode, the receiver gets every frame again
+                pub fn disable_mute_mode(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.mme().clear_bit());
+                }
+
+                /// Mutes the receiver until the configured wakeup condition occurs
+                pub fn mute(&mut self) {
+                    self.usart.rqr.write(|w| w.mmrq().set_bit());
+                }
+
+                /// Returns whether the receiver is currently muted
+                pub fn is_muted(&self) -> bool {
+                    self.usart.isr.read().rwu().bit_is_set()
+                }
+
                 /// Splits the `Serial` abstraction into a transmitter and a receiver half
-                pub fn split(self) -> (Tx<$USARTX>, Rx<$USARTX>) {
+                pub fn split(self) -> (Tx<$USARTX, MODE>, Rx<$USARTX, MODE>) {
                     (
                         Tx {
                             _usart: PhantomData,
+                            _mode: PhantomData,
                         },
                         Rx {
                             _usart: PhantomData,
+                            _mode: PhantomData,
                         },
                     )
                 }
 
                 /// Releases the USART peripheral and associated pins
-                pub fn free(self) -> ($USARTX, (TX, RX)) {
+                pub fn free(self) -> ($USARTX, PINS) {
+                    (self.usart, self.pins)
+                }
+            }
+
+            impl<CK, MISO, MOSI> UsartSpi<$USARTX, (CK, MISO, MOSI)> {
+                /// Configures a USART peripheral as a synchronous SPI master
+                pub fn $usartX<F>(
+                    usart: $USARTX,
+                    pins: (CK, MISO, MOSI),
+                    mode: Mode,
+                    freq: F,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    F: Into<Hertz>,
+                    CK: CkPin<$USARTX>,
+                    MISO: RxPin<$USARTX>,
+                    MOSI: TxPin<$USARTX>,
+                {
+                    // enable or reset $USARTX
+                    apb.enr().modify(|_, w| w.$usartXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$usartXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$usartXrst().clear_bit());
+
+                    // Word length = 8 bit
+                    usart.cr1.write(|w|  w.m0().clear_bit().m1().clear_bit());
+
+                    // Configure the clock frequency, in synchronous mode CK runs at the baud rate
+                    let brr = clocks.$pclkX().0 / freq.into().0;
+                    assert!(brr >= 16, "impossible clock frequency");
+                    usart.brr.write(|w| unsafe { w.bits(brr) });
+
+                    // CLKEN: output the clock on CK
+                    // LBCL: also clock out the last data bit, an SPI slave needs all 8 edges
+                    // MSBFIRST: SPI devices expect the most significant bit first
+                    usart.cr2.write(|w| {
+                        w.stop()
+                            .bits(0b00)
+                            .clken()
+                            .set_bit()
+                            .lbcl()
+                            .set_bit()
+                            .msbfirst()
+                            .set_bit()
+                            .cpol()
+                            .bit(mode.polarity == Polarity::IdleHigh)
+                            .cpha()
+                            .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                    });
+
+                    // UE: enable USART
+                    // RE: enable receiver
+                    // TE: enable transceiver
+                    usart
+                        .cr1
+                        .write(|w| w.ue().set_bit().re().set_bit().te().set_bit());
+
+                    UsartSpi { usart, pins }
+                }
+
+                /// Releases the USART peripheral and associated pins
+                pub fn free(self) -> ($USARTX, (CK, MISO, MOSI)) {
                     (self.usart, self.pins)
                 }
             }
 
-            impl serial::Read<u8> for Rx<$USARTX> {
+            impl<PINS> FullDuplex<u8> for UsartSpi<$USARTX, PINS> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    let isr = self.usart.isr.read();
+
+                    Err(if isr.ore().bit_is_set() {
+                        nb::Error::Other(Error::Overrun)
+                    } else if isr.rxne().bit_is_set() {
+                        // NOTE(read_volatile) see `write_volatile` below
+                        return Ok(unsafe {
+                            ptr::read_volatile(&self.usart.rdr as *const _ as *const _)
+                        });
+                    } else {
+                        nb::Error::WouldBlock
+                    })
+                }
+
+                fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    let isr = self.usart.isr.read();
+
+                    if isr.txe().bit_is_set() {
+                        // NOTE(write_volatile) 8-bit write that's not possible through the svd2rust API
+                        unsafe {
+                            ptr::write_volatile(ptr::addr_of!(self.usart.tdr) as *mut u8, byte)
+                        }
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl<PINS> hal::blocking::spi::transfer::Default<u8> for UsartSpi<$USARTX, PINS> {}
+
+            impl<PINS> hal::blocking::spi::write::Default<u8> for UsartSpi<$USARTX, PINS> {}
+
+            impl<MODE> serial::Read<u16> for Rx<$USARTX, MODE> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u16, Error> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+
+                    Err(if isr.pe().bit_is_set() {
+                        nb::Error::Other(Error::Parity)
+                    } else if isr.fe().bit_is_set() {
+                        nb::Error::Other(Error::Framing)
+                    } else if isr.nf().bit_is_set() {
+                        nb::Error::Other(Error::Noise)
+                    } else if isr.ore().bit_is_set() {
+                        nb::Error::Other(Error::Overrun)
+                    } else if isr.rxne().bit_is_set() {
+                        // NOTE(unsafe) atomic read from stateless register
+                        return Ok(unsafe { (*$USARTX::ptr()).rdr.read().rdr().bits() });
+                    } else {
+                        nb::Error::WouldBlock
+                    })
+                }
+            }
+
+            impl<MODE> serial::Read<u8> for Rx<$USARTX, MODE> {
                 type Error = Error;
 
                 fn read(&mut self) -> nb::Result<u8, Error> {
@@ -207,36 +1014,79 @@ macro_rules! hal {
                 }
             }
 
-            impl serial::Write<u8> for Tx<$USARTX> {
-                // NOTE(Void) See section "29.7 USART interrupts"; the only possible errors during
-                // transmission are: clear to send (which is disabled in this case) errors and
-                // framing errors (which only occur in SmartCard mode); neither of these apply to
-                // our hardware configuration
-                type Error = Void;
+            impl lin::DetectBreak for Rx<$USARTX> {
+                fn read_break(&mut self) -> nb::Result<(), Void> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+
+                    if isr.lbdf().bit_is_set() {
+                        // NOTE(unsafe) atomic writes to stateless registers
+                        // The break itself is also seen as a framing error and a 0x00 data byte
+                        unsafe {
+                            (*$USARTX::ptr()).icr.write(|w| w.lbdcf().set_bit().fecf().set_bit());
+                            (*$USARTX::ptr()).rqr.write(|w| w.rxfrq().set_bit());
+                        }
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl lin::SendBreak for Tx<$USARTX> {
+                fn send_break(&mut self) -> nb::Result<(), Void> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+
+                    if isr.sbkf().bit_is_set() {
+                        // a previously requested break is still being sent
+                        Err(nb::Error::WouldBlock)
+                    } else {
+                        // NOTE(unsafe) atomic write to stateless register
+                        unsafe { (*$USARTX::ptr()).rqr.write(|w| w.sbkrq().set_bit()) };
+                        Ok(())
+                    }
+                }
+            }
+
+            tx_void!($USARTX, Asynchronous);
+            tx_void!($USARTX, HalfDuplex);
+            tx_void!($USARTX, IrDA);
+
+            impl serial::Write<u8> for Tx<$USARTX, SmartCard> {
+                // NOTE(Error) in smartcard mode a framing error is reported once the card
+                // NACKed a byte more often than the configured number of retries
+                type Error = Error;
 
-                fn flush(&mut self) -> nb::Result<(), Void> {
+                fn flush(&mut self) -> nb::Result<(), Error> {
                     // NOTE(unsafe) atomic read with no side effects
                     let isr = unsafe { (*$USARTX::ptr()).isr.read() };
 
-                    if isr.tc().bit_is_set() {
+                    if isr.fe().bit_is_set() {
+                        // NOTE(unsafe) atomic write to stateless register
+                        unsafe { (*$USARTX::ptr()).icr.write(|w| w.fecf().set_bit()) };
+                        Err(nb::Error::Other(Error::Framing))
+                    } else if isr.tc().bit_is_set() {
                         Ok(())
                     } else {
                         Err(nb::Error::WouldBlock)
                     }
                 }
 
-                fn write(&mut self, byte: u8) -> nb::Result<(), Void> {
+                fn write(&mut self, byte: u8) -> nb::Result<(), Error> {
                     // NOTE(unsafe) atomic read with no side effects
                     let isr = unsafe { (*$USARTX::ptr()).isr.read() };
 
-                    if isr.txe().bit_is_set() {
+                    if isr.fe().bit_is_set() {
+                        // NOTE(unsafe) atomic write to stateless register
+                        unsafe { (*$USARTX::ptr()).icr.write(|w| w.fecf().set_bit()) };
+                        Err(nb::Error::Other(Error::Framing))
+                    } else if isr.txe().bit_is_set() {
                         // NOTE(unsafe) atomic write to stateless register
                         // NOTE(write_volatile) 8-bit write that's not possible through the svd2rust API
                         unsafe {
-                            ptr::write_volatile(&(*$USARTX::ptr()).tdr as *const _ as *mut _, byte)
+                            ptr::write_volatile(ptr::addr_of!((*$USARTX::ptr()).tdr) as *mut u8, byte)
                         }
-
-                        // NOTE(point 8) we maybe gotta implement point 8 on page 2031 of the reference manual here if we encounter bugs
                         Ok(())
                     } else {
                         Err(nb::Error::WouldBlock)
@@ -248,8 +1098,8 @@ macro_rules! hal {
 }
 
 hal! {
-    USART1: (usart1, APB2, usart1en, usart1rst, pclk2),
-    USART2: (usart2, APB1L, usart2en, usart2rst, pclk1),
-    USART3: (usart3, APB1L, usart3en, usart3rst, pclk1),
-    USART6: (usart6, APB2, usart6en, usart6rst, pclk2),
+    USART1: (usart1, usart1_half_duplex, usart1_smartcard, usart1_irda, APB2, usart1en, usart1rst, pclk2, usart16),
+    USART2: (usart2, usart2_half_duplex, usart2_smartcard, usart2_irda, APB1L, usart2en, usart2rst, pclk1, usart234578),
+    USART3: (usart3, usart3_half_duplex, usart3_smartcard, usart3_irda, APB1L, usart3en, usart3rst, pclk1, usart234578),
+    USART6: (usart6, usart6_half_duplex, usart6_smartcard, usart6_irda, APB2, usart6en, usart6rst, pclk2, usart16),
 }
\ No newline at end of file
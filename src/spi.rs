@@ -0,0 +1,1286 @@
+//! Serial Peripheral Interface implementation
+//!
+//! The SPI peripherals of the H7 exchange frames of 4 to 32 bits through FIFOs. Transfers of a
+//! known length are programmed into TSIZE, so the peripheral stops clocking and signals the end
+//! of the transfer by itself. The `FullDuplex` implementation runs in endless mode instead.
+//!
+//! `Spi` operates as master, `SpiSlave` as slave selected by the hardware NSS input.
+//!
+//! SPI1 to SPI5 can move the frames of a master with the DMA streams of DMA1 and DMA2, either
+//! in transfers of a known length or circularly into two alternating buffers. SPI6 is only
+//! connected to the BDMA.
+
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{self, Ordering};
+
+use crate::gpio::gpioa::{PA4, PA5, PA6, PA7, PA9, PA11, PA12, PA15};
+use crate::gpio::gpiob::{PB2, PB3, PB4, PB5, PB9, PB10, PB12, PB13, PB14, PB15};
+use crate::gpio::gpioc::{PC1, PC2, PC3, PC10, PC11, PC12};
+use crate::gpio::gpiod::{PD3, PD6, PD7};
+use crate::gpio::gpioe::{PE2, PE4, PE5, PE6, PE11, PE12, PE13, PE14};
+#[cfg(feature = "gpio-fg")]
+use crate::gpio::gpiof::{PF6, PF7, PF8, PF9, PF11};
+#[cfg(feature = "gpio-fg")]
+use crate::gpio::gpiog::{PG8, PG9, PG10, PG11, PG12, PG13, PG14};
+#[cfg(feature = "gpio-hi")]
+use crate::gpio::gpioh::{PH5, PH6, PH7};
+#[cfg(feature = "gpio-hi")]
+use crate::gpio::gpioi::{PI0, PI1, PI2, PI3};
+#[cfg(feature = "gpio-jk")]
+use crate::gpio::gpioj::{PJ10, PJ11};
+#[cfg(feature = "gpio-jk")]
+use crate::gpio::gpiok::{PK0, PK1};
+use crate::dma::{self, Stream};
+use crate::gpio::{Alternate, Locked, AF5, AF6, AF7, AF8};
+use crate::rcc::{Clocks, APB1L, APB2, APB4};
+use crate::time::Hertz;
+use hal::spi::{FullDuplex, Mode, Phase, Polarity};
+use nb::{self, block};
+use stm32h7::stm32h7x3::{spi1, SPI1, SPI2, SPI3, SPI4, SPI5, SPI6};
+
+/// SPI error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// A frame was received while the RX FIFO was full
+    Overrun,
+    /// The NSS input was pulled low while operating as master
+    ModeFault,
+    /// The received CRC did not match
+    Crc,
+    /// The master clocked a frame before one was written to the TX FIFO, slave mode only
+    Underrun,
+    /// NSS changed in the middle of a TI frame, TI mode only
+    TiFrame,
+    /// A DMA stream signaled a transfer error
+    Dma,
+    /// The kernel clock is stopped or its frequency unknown, select another one with `CCIPR`
+    KernelClock,
+    /// The baud rate is zero or below the kernel clock divided by 256
+    Frequency,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Largest number of frames a single transfer can be programmed with
+const MAX_TSIZE: usize = 0xffff;
+
+/// A trait to represent the SCK Pin of an SPI Port
+///
+/// # Safety
+///
+/// Only implement it for a pin in the alternate function that connects it to the SCK
+/// signal of `SPI`.
+pub unsafe trait SckPin<SPI> {}
+
+/// A trait to represent the MISO Pin of an SPI Port
+///
+/// # Safety
+///
+/// Only implement it for a pin in the alternate function that connects it to the MISO
+/// signal of `SPI`.
+pub unsafe trait MisoPin<SPI> {}
+
+/// A trait to represent the MOSI Pin of an SPI Port
+///
+/// # Safety
+///
+/// Only implement it for a pin in the alternate function that connects it to the MOSI
+/// signal of `SPI`.
+pub unsafe trait MosiPin<SPI> {}
+
+/// A trait to represent the NSS Pin of an SPI Port
+///
+/// # Safety
+///
+/// Only implement it for a pin in the alternate function that connects it to the NSS
+/// signal of `SPI`.
+pub unsafe trait NssPin<SPI> {}
+
+/// Placeholder for an unused MISO pin, e.g. for a display that is only written
+pub struct NoMiso;
+
+/// Placeholder for an unused MOSI pin, e.g. for a sensor that is only read
+pub struct NoMosi;
+
+unsafe impl<SPI> MisoPin<SPI> for NoMiso {}
+unsafe impl<SPI> MosiPin<SPI> for NoMosi {}
+
+unsafe impl<SPI, PIN: SckPin<SPI>> SckPin<SPI> for Locked<PIN> {}
+unsafe impl<SPI, PIN: MisoPin<SPI>> MisoPin<SPI> for Locked<PIN> {}
+unsafe impl<SPI, PIN: MosiPin<SPI>> MosiPin<SPI> for Locked<PIN> {}
+unsafe impl<SPI, PIN: NssPin<SPI>> NssPin<SPI> for Locked<PIN> {}
+
+// SPI1
+unsafe impl SckPin<SPI1> for PA5<Alternate<AF5>> {}
+unsafe impl SckPin<SPI1> for PB3<Alternate<AF5>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl SckPin<SPI1> for PG11<Alternate<AF5>> {}
+unsafe impl MisoPin<SPI1> for PA6<Alternate<AF5>> {}
+unsafe impl MisoPin<SPI1> for PB4<Alternate<AF5>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl MisoPin<SPI1> for PG9<Alternate<AF5>> {}
+unsafe impl MosiPin<SPI1> for PA7<Alternate<AF5>> {}
+unsafe impl MosiPin<SPI1> for PB5<Alternate<AF5>> {}
+unsafe impl MosiPin<SPI1> for PD7<Alternate<AF5>> {}
+
+unsafe impl NssPin<SPI1> for PA4<Alternate<AF5>> {}
+unsafe impl NssPin<SPI1> for PA15<Alternate<AF5>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl NssPin<SPI1> for PG10<Alternate<AF5>> {}
+
+// SPI2
+unsafe impl SckPin<SPI2> for PA9<Alternate<AF5>> {}
+unsafe impl SckPin<SPI2> for PA12<Alternate<AF5>> {}
+unsafe impl SckPin<SPI2> for PB10<Alternate<AF5>> {}
+unsafe impl SckPin<SPI2> for PB13<Alternate<AF5>> {}
+unsafe impl SckPin<SPI2> for PD3<Alternate<AF5>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl SckPin<SPI2> for PI1<Alternate<AF5>> {}
+unsafe impl MisoPin<SPI2> for PB14<Alternate<AF5>> {}
+unsafe impl MisoPin<SPI2> for PC2<Alternate<AF5>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl MisoPin<SPI2> for PI2<Alternate<AF5>> {}
+unsafe impl MosiPin<SPI2> for PB15<Alternate<AF5>> {}
+unsafe impl MosiPin<SPI2> for PC1<Alternate<AF5>> {}
+unsafe impl MosiPin<SPI2> for PC3<Alternate<AF5>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl MosiPin<SPI2> for PI3<Alternate<AF5>> {}
+
+unsafe impl NssPin<SPI2> for PA11<Alternate<AF5>> {}
+unsafe impl NssPin<SPI2> for PB4<Alternate<AF7>> {}
+unsafe impl NssPin<SPI2> for PB9<Alternate<AF5>> {}
+unsafe impl NssPin<SPI2> for PB12<Alternate<AF5>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl NssPin<SPI2> for PI0<Alternate<AF5>> {}
+
+// SPI3
+unsafe impl SckPin<SPI3> for PB3<Alternate<AF6>> {}
+unsafe impl SckPin<SPI3> for PC10<Alternate<AF6>> {}
+unsafe impl MisoPin<SPI3> for PB4<Alternate<AF6>> {}
+unsafe impl MisoPin<SPI3> for PC11<Alternate<AF6>> {}
+unsafe impl MosiPin<SPI3> for PB2<Alternate<AF7>> {}
+unsafe impl MosiPin<SPI3> for PB5<Alternate<AF7>> {}
+unsafe impl MosiPin<SPI3> for PC12<Alternate<AF6>> {}
+unsafe impl MosiPin<SPI3> for PD6<Alternate<AF5>> {}
+
+unsafe impl NssPin<SPI3> for PA4<Alternate<AF6>> {}
+unsafe impl NssPin<SPI3> for PA15<Alternate<AF6>> {}
+
+// SPI4
+unsafe impl SckPin<SPI4> for PE2<Alternate<AF5>> {}
+unsafe impl SckPin<SPI4> for PE12<Alternate<AF5>> {}
+unsafe impl MisoPin<SPI4> for PE5<Alternate<AF5>> {}
+unsafe impl MisoPin<SPI4> for PE13<Alternate<AF5>> {}
+unsafe impl MosiPin<SPI4> for PE6<Alternate<AF5>> {}
+unsafe impl MosiPin<SPI4> for PE14<Alternate<AF5>> {}
+
+unsafe impl NssPin<SPI4> for PE4<Alternate<AF5>> {}
+unsafe impl NssPin<SPI4> for PE11<Alternate<AF5>> {}
+
+// SPI5
+#[cfg(feature = "gpio-fg")]
+unsafe impl SckPin<SPI5> for PF7<Alternate<AF5>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl SckPin<SPI5> for PH6<Alternate<AF5>> {}
+#[cfg(feature = "gpio-jk")]
+unsafe impl SckPin<SPI5> for PK0<Alternate<AF5>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl MisoPin<SPI5> for PF8<Alternate<AF5>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl MisoPin<SPI5> for PH7<Alternate<AF5>> {}
+#[cfg(feature = "gpio-jk")]
+unsafe impl MisoPin<SPI5> for PJ11<Alternate<AF5>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl MosiPin<SPI5> for PF9<Alternate<AF5>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl MosiPin<SPI5> for PF11<Alternate<AF5>> {}
+#[cfg(feature = "gpio-jk")]
+unsafe impl MosiPin<SPI5> for PJ10<Alternate<AF5>> {}
+
+#[cfg(feature = "gpio-fg")]
+unsafe impl NssPin<SPI5> for PF6<Alternate<AF5>> {}
+#[cfg(feature = "gpio-hi")]
+unsafe impl NssPin<SPI5> for PH5<Alternate<AF5>> {}
+#[cfg(feature = "gpio-jk")]
+unsafe impl NssPin<SPI5> for PK1<Alternate<AF5>> {}
+
+// SPI6
+unsafe impl SckPin<SPI6> for PA5<Alternate<AF8>> {}
+unsafe impl SckPin<SPI6> for PB3<Alternate<AF8>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl SckPin<SPI6> for PG13<Alternate<AF5>> {}
+unsafe impl MisoPin<SPI6> for PA6<Alternate<AF8>> {}
+unsafe impl MisoPin<SPI6> for PB4<Alternate<AF8>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl MisoPin<SPI6> for PG12<Alternate<AF5>> {}
+unsafe impl MosiPin<SPI6> for PA7<Alternate<AF8>> {}
+unsafe impl MosiPin<SPI6> for PB5<Alternate<AF8>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl MosiPin<SPI6> for PG14<Alternate<AF5>> {}
+unsafe impl NssPin<SPI6> for PA4<Alternate<AF8>> {}
+unsafe impl NssPin<SPI6> for PA15<Alternate<AF7>> {}
+#[cfg(feature = "gpio-fg")]
+unsafe impl NssPin<SPI6> for PG8<Alternate<AF5>> {}
+
+/// Data type of a frame - DO NOT IMPLEMENT THIS TRAIT
+///
+/// The data registers are accessed with the size of this type, every access transfers exactly
+/// one frame. It has to be the smallest type the frame size fits in.
+///
+/// # Safety
+///
+/// `BITS` and `DMA_SIZE` have to match the size of the type, the data registers are
+/// accessed and the DMA writes to the buffers with that size.
+pub unsafe trait Word: Copy + Default {
+    /// Number of bits of the type
+    const BITS: u8;
+
+    /// Size of the DMA data items moving the frames
+    const DMA_SIZE: dma::Size;
+}
+
+unsafe impl Word for u8 {
+    const BITS: u8 = 8;
+    const DMA_SIZE: dma::Size = dma::Size::Byte;
+}
+
+unsafe impl Word for u16 {
+    const BITS: u8 = 16;
+    const DMA_SIZE: dma::Size = dma::Size::HalfWord;
+}
+
+unsafe impl Word for u32 {
+    const BITS: u8 = 32;
+    const DMA_SIZE: dma::Size = dma::Size::Word;
+}
+
+/// Frame format
+#[derive(Clone, Copy, PartialEq)]
+pub enum FrameFormat {
+    /// Motorola format, the clock polarity and phase are selected by the `Mode`
+    Motorola,
+    /// TI format, NSS pulses before every frame and the `Mode` is ignored
+    Ti,
+}
+
+/// Configuration of the NSS output of a master
+#[derive(Clone, Copy)]
+pub struct NssConfig {
+    /// Pulse NSS inactive between consecutive frames
+    pub pulse: bool,
+    /// NSS is active high instead of low
+    pub active_high: bool,
+    /// Clock cycles between NSS becoming active and the first frame, 0 to 15
+    pub mssi: u8,
+    /// Idle clock cycles inserted between consecutive frames, 0 to 15
+    pub midi: u8,
+}
+
+/// Data a slave sends when the master clocks a frame while the TX FIFO is empty
+#[derive(Clone, Copy, PartialEq)]
+pub enum UnderrunData {
+    /// A constant pattern
+    Pattern(u32),
+    /// The frame that was received last
+    LastReceived,
+    /// The frame that was sent last
+    LastTransmitted,
+}
+
+/// SPI peripheral operating as master
+pub struct Spi<SPI, PINS, WORD = u8> {
+    spi: SPI,
+    pins: PINS,
+    threshold: u8,
+    _word: PhantomData<WORD>,
+}
+
+/// SPI peripheral operating as slave
+///
+/// The slave is selected by the hardware NSS input, the pins are `(SCK, MISO, MOSI, NSS)`.
+pub struct SpiSlave<SPI, PINS, WORD = u8> {
+    spi: SPI,
+    pins: PINS,
+    threshold: u8,
+    _word: PhantomData<WORD>,
+}
+
+/// DMA driven SPI transfer of a known length
+///
+/// The peripheral signals the end of the transfer with EOT. `poll` has to be called from the
+/// SPI interrupt, or in a loop, until it returns the result of the transfer.
+pub struct Transfer<SPI, PINS, WORD, BUFFERS, STREAMS> {
+    spi: Spi<SPI, PINS, WORD>,
+    buffers: BUFFERS,
+    streams: STREAMS,
+    result: Option<Result<(), Error>>,
+    callback: Option<fn(Result<(), Error>)>,
+}
+
+impl<SPI, PINS, WORD, BUFFERS, STREAMS> Transfer<SPI, PINS, WORD, BUFFERS, STREAMS> {
+    /// Sets a function that is called with the result once the transfer is done
+    pub fn on_complete(&mut self, callback: fn(Result<(), Error>)) {
+        self.callback = Some(callback);
+    }
+
+    /// Returns whether the transfer is done
+    pub fn is_done(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// Releases the SPI peripheral, the buffers and the DMA streams
+    ///
+    /// Panics if the transfer is not done yet.
+    pub fn free(self) -> (Spi<SPI, PINS, WORD>, BUFFERS, STREAMS) {
+        assert!(self.is_done());
+
+        (self.spi, self.buffers, self.streams)
+    }
+
+    /// Calls the callback with the result of the transfer
+    fn notify(&self) {
+        if let (Some(callback), Some(result)) = (self.callback, self.result) {
+            callback(result);
+        }
+    }
+}
+
+/// Circular DMA reception of a master into two alternating buffers
+///
+/// The master clocks frames continuously. While the DMA fills one buffer, the other one can
+/// be processed with `read`.
+pub struct CircularRead<SPI, PINS, WORD: 'static, STREAM> {
+    spi: Spi<SPI, PINS, WORD>,
+    buffers: (&'static mut [WORD], &'static mut [WORD]),
+    stream: STREAM,
+}
+
+impl<SPI, PINS, WORD, STREAM> CircularRead<SPI, PINS, WORD, STREAM>
+where
+    STREAM: Stream,
+{
+    /// Passes the buffer filled last to `f`
+    ///
+    /// Returns `Error::Overrun` if the DMA started to overwrite the buffer before `f` returned,
+    /// the next call continues with the following buffer.
+    pub fn read<F, R>(&mut self, f: F) -> nb::Result<R, Error>
+    where
+        F: FnOnce(&[WORD]) -> R,
+    {
+        if self.stream.has_error() {
+            return Err(nb::Error::Other(Error::Dma));
+        }
+        if !self.stream.is_complete() {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.stream.clear_flags();
+        atomic::compiler_fence(Ordering::SeqCst);
+
+        // The stream already switched over to the other buffer
+        let buffer = match self.stream.current_memory() {
+            0 => &*self.buffers.1,
+            _ => &*self.buffers.0,
+        };
+        let result = f(buffer);
+
+        atomic::compiler_fence(Ordering::SeqCst);
+        if self.stream.is_complete() {
+            Err(nb::Error::Other(Error::Overrun))
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+/// DMA streams moving the frames of a `Transfer`, a single stream or a `(TX, RX)` pair
+pub trait TransferStreams {
+    /// Returns whether all streams moved all their data items
+    fn is_complete(&self) -> bool;
+
+    /// Returns whether a stream signaled an error
+    fn has_error(&self) -> bool;
+
+    /// Stops the streams that are still enabled
+    fn disable(&mut self);
+}
+
+impl<S: Stream> TransferStreams for S {
+    fn is_complete(&self) -> bool {
+        Stream::is_complete(self)
+    }
+
+    fn has_error(&self) -> bool {
+        Stream::has_error(self)
+    }
+
+    fn disable(&mut self) {
+        if self.is_enabled() {
+            Stream::disable(self);
+        }
+    }
+}
+
+impl<TX: Stream, RX: Stream> TransferStreams for (TX, RX) {
+    fn is_complete(&self) -> bool {
+        self.0.is_complete() && self.1.is_complete()
+    }
+
+    fn has_error(&self) -> bool {
+        self.0.has_error() || self.1.has_error()
+    }
+
+    fn disable(&mut self) {
+        TransferStreams::disable(&mut self.0);
+        TransferStreams::disable(&mut self.1);
+    }
+}
+
+/// COMM bits of a full duplex transfer
+const COMM_FULL_DUPLEX: u8 = 0b00;
+/// COMM bits of a transfer that only drives MOSI
+const COMM_TRANSMITTER: u8 = 0b01;
+/// COMM bits of a transfer that only samples MISO
+const COMM_RECEIVER: u8 = 0b10;
+
+/// Returns the MBR bits of the smallest prescaler that divides `ker_ck` down to `freq` or below
+fn prescaler(ker_ck: Hertz, freq: Hertz) -> Result<u8, Error> {
+    if freq.0 == 0 {
+        return Err(Error::Frequency);
+    }
+
+    match ker_ck.0.div_ceil(freq.0) {
+        0..=2 => Ok(0b000),
+        3..=4 => Ok(0b001),
+        5..=8 => Ok(0b010),
+        9..=16 => Ok(0b011),
+        17..=32 => Ok(0b100),
+        33..=64 => Ok(0b101),
+        65..=128 => Ok(0b110),
+        129..=256 => Ok(0b111),
+        _ => Err(Error::Frequency),
+    }
+}
+
+/// Returns and clears the error flagged in the status register
+fn check_errors(spi: &spi1::RegisterBlock, sr: &spi1::sr::R) -> Result<(), Error> {
+    if sr.ovr().bit_is_set() {
+        spi.ifcr.write(|w| w.ovrc().set_bit());
+        Err(Error::Overrun)
+    } else if sr.modf().bit_is_set() {
+        spi.ifcr.write(|w| w.modfc().set_bit());
+        Err(Error::ModeFault)
+    } else if sr.crce().bit_is_set() {
+        spi.ifcr.write(|w| w.crcec().set_bit());
+        Err(Error::Crc)
+    } else if sr.udr().bit_is_set() {
+        spi.ifcr.write(|w| w.udrc().set_bit());
+        Err(Error::Underrun)
+    } else if sr.tifre().bit_is_set() {
+        spi.ifcr.write(|w| w.tifrec().set_bit());
+        Err(Error::TiFrame)
+    } else {
+        Ok(())
+    }
+}
+
+/// Restarts the peripheral for a transfer of `tsize` frames, 0 being an endless transfer
+///
+/// Disabling the peripheral flushes both FIFOs.
+fn start(spi: &spi1::RegisterBlock, tsize: u16) {
+    spi.cr1.modify(|_, w| w.spe().clear_bit());
+    spi.ifcr.write(|w| w.eotc().set_bit().txtfc().set_bit());
+    spi.cr2.write(|w| unsafe { w.tsize().bits(tsize) });
+    spi.cr1.modify(|_, w| w.spe().set_bit());
+    // A slave starts with the clock of the master
+    if spi.cfg2.read().master().bit_is_set() {
+        // NOTE(unsafe) CSTART, bit 9, is missing from the writer of the PAC
+        spi.cr1.modify(|r, w| unsafe { w.bits(r.bits() | (1 << 9)) });
+    }
+}
+
+/// Returns the configuration of a stream moving `len` frames between `memory` and `register`
+fn dma_config<W: Word>(
+    request: u8,
+    direction: dma::Direction,
+    register: u32,
+    memory: u32,
+    len: usize,
+) -> dma::Config {
+    dma::Config {
+        request,
+        direction,
+        peripheral: register,
+        memory,
+        len: len as u16,
+        size: W::DMA_SIZE,
+        memory_increment: true,
+        circular: false,
+        memory1: None,
+    }
+}
+
+/// Starts a DMA transfer of `tsize` frames in the direction `comm`
+///
+/// The peripheral has to be disabled with the DMA requests and streams set up before, as the
+/// streams are served as soon as it is enabled again. A transfer of a known length signals its
+/// end with the EOT interrupt.
+fn start_dma(spi: &spi1::RegisterBlock, tsize: u16, comm: u8) {
+    spi.cfg2.modify(|_, w| unsafe { w.comm().bits(comm) });
+    if tsize != 0 {
+        spi.ier.write(|w| w.eotie().set_bit().modfie().set_bit());
+    }
+    start(spi, tsize);
+}
+
+/// Ends a DMA transfer, suspending it if the master is still clocking, and restarts the
+/// peripheral in endless full duplex mode
+fn stop_dma(spi: &spi1::RegisterBlock) {
+    spi.ier.reset();
+    // CSTART is cleared by the peripheral at the end of a transfer
+    if spi.cr1.read().cstart().bit_is_set() {
+        spi.cr1.modify(|_, w| w.csusp().set_bit());
+        while spi.sr.read().susp().bit_is_clear() {}
+        spi.ifcr.write(|w| w.suspc().set_bit());
+    }
+    spi.cr1.modify(|_, w| w.spe().clear_bit());
+    spi.cfg1.modify(|_, w| w.rxdmaen().clear_bit().txdmaen().clear_bit());
+    spi.cfg2.modify(|_, w| unsafe { w.comm().bits(COMM_FULL_DUPLEX) });
+    start(spi, 0);
+}
+
+/// Disables the peripheral, so `f` can change its configuration, and restarts it in endless mode
+fn reconfigure<F>(spi: &spi1::RegisterBlock, f: F)
+where
+    F: FnOnce(&spi1::RegisterBlock),
+{
+    spi.cr1.modify(|_, w| w.spe().clear_bit());
+    f(spi);
+    start(spi, 0);
+}
+
+/// Returns whether `frames` frames of `bits` fit into a FIFO of `fifo` bytes
+///
+/// Frames take up 1, 2 or 4 bytes of the FIFO, depending on their size.
+fn fits_fifo(frames: u8, bits: u8, fifo: u8) -> bool {
+    let bytes = match bits {
+        0..=8 => 1,
+        9..=16 => 2,
+        _ => 4,
+    };
+
+    u16::from(frames) * bytes <= u16::from(fifo)
+}
+
+/// Changes the size of the frames to `bits`, checking that `W` is the type they fit in and
+/// that the FIFO threshold still fits into the FIFO of `fifo` bytes
+fn set_frame_size<W: Word>(spi: &spi1::RegisterBlock, bits: u8, fifo: u8) {
+    assert!((4..=W::BITS).contains(&bits));
+    assert!(W::BITS == 8 || bits > W::BITS / 2);
+    assert!(fits_fifo(spi.cfg1.read().fthvl().bits() + 1, bits, fifo));
+
+    reconfigure(spi, |spi| spi.cfg1.modify(|_, w| unsafe { w.dsize().bits(bits - 1) }));
+}
+
+/// Sets the FIFO threshold to `frames`, from 1 to 16, checking that they fit into the FIFO
+/// of `fifo` bytes
+fn set_fifo_threshold(spi: &spi1::RegisterBlock, frames: u8, fifo: u8) {
+    assert!((1..=16).contains(&frames));
+    assert!(fits_fifo(frames, spi.cfg1.read().dsize().bits() + 1, fifo));
+
+    reconfigure(spi, |spi| spi.cfg1.modify(|_, w| unsafe { w.fthvl().bits(frames - 1) }));
+}
+
+/// Enables the CRC of `bits`, from 4 to 32, with `polynomial`, or disables it
+fn set_crc(spi: &spi1::RegisterBlock, crc: Option<(u32, u8)>) {
+    reconfigure(spi, |spi| match crc {
+        Some((polynomial, bits)) => {
+            assert!((4..=32).contains(&bits));
+
+            spi.crcpoly.write(|w| unsafe { w.bits(polynomial) });
+            spi.cfg1.modify(|_, w| unsafe { w.crcsize().bits(bits - 1).crcen().set_bit() });
+        }
+        None => spi.cfg1.modify(|_, w| w.crcen().clear_bit()),
+    });
+}
+
+/// Selects the frame format
+fn set_frame_format(spi: &spi1::RegisterBlock, format: FrameFormat) {
+    let sp = match format {
+        FrameFormat::Motorola => 0b000,
+        FrameFormat::Ti => 0b001,
+    };
+    reconfigure(spi, |spi| spi.cfg2.modify(|_, w| unsafe { w.sp().bits(sp) }));
+}
+
+/// Returns whether a frame can be read from the RX FIFO, even below the FIFO threshold
+fn rx_available(sr: &spi1::sr::R) -> bool {
+    sr.rxp().bit_is_set() || sr.rxwne().bit_is_set() || sr.rxplvl().bits() != 0
+}
+
+/// Reads a frame from the RX FIFO
+fn read_frame<W: Word>(spi: &spi1::RegisterBlock) -> W {
+    // NOTE(read_volatile) the size of the access decides how many frames are popped
+    unsafe { ptr::read_volatile(ptr::addr_of!(spi.rxdr) as *const W) }
+}
+
+/// Writes a frame to the TX FIFO
+fn write_frame<W: Word>(spi: &spi1::RegisterBlock, word: W) {
+    // NOTE(write_volatile) the size of the access decides how many frames are pushed
+    unsafe { ptr::write_volatile(ptr::addr_of!(spi.txdr) as *mut W, word) }
+}
+
+/// Exchanges `words` with the received frames in a single transfer
+///
+/// At most `threshold` frames are in flight, so the RX FIFO can't overrun.
+fn transfer_chunk<W: Word>(
+    spi: &spi1::RegisterBlock,
+    words: &mut [W],
+    threshold: usize,
+) -> Result<(), Error> {
+    let len = words.len();
+    let (mut tx, mut rx) = (0, 0);
+
+    start(spi, len as u16);
+    while rx < len {
+        let sr = spi.sr.read();
+        check_errors(spi, &sr)?;
+
+        if tx < len && tx - rx < threshold && sr.txp().bit_is_set() {
+            write_frame(spi, words[tx]);
+            tx += 1;
+        }
+        if rx < tx && rx_available(&sr) {
+            words[rx] = read_frame(spi);
+            rx += 1;
+        }
+    }
+
+    while spi.sr.read().eot().bit_is_clear() {}
+    // The CRC is checked at the end of the transfer
+    check_errors(spi, &spi.sr.read())
+}
+
+/// Sends `words` in a single transfer, discarding the received frames
+fn write_chunk<W: Word>(
+    spi: &spi1::RegisterBlock,
+    words: &[W],
+    threshold: usize,
+) -> Result<(), Error> {
+    let len = words.len();
+    let (mut tx, mut rx) = (0, 0);
+
+    start(spi, len as u16);
+    while rx < len {
+        let sr = spi.sr.read();
+        check_errors(spi, &sr)?;
+
+        if tx < len && tx - rx < threshold && sr.txp().bit_is_set() {
+            write_frame(spi, words[tx]);
+            tx += 1;
+        }
+        if rx < tx && rx_available(&sr) {
+            read_frame::<W>(spi);
+            rx += 1;
+        }
+    }
+
+    while spi.sr.read().eot().bit_is_clear() {}
+    // The CRC is checked at the end of the transfer
+    check_errors(spi, &spi.sr.read())
+}
+
+/// Implements the blocking traits with TSIZE transfers for every data type
+///
+/// A generic implementation would overlap with the default implementations of embedded-hal.
+macro_rules! spi_blocking {
+    ($Spi:ident, $SPIX:ident: [$($WORD:ty),+]) => {
+        $(
+        impl<PINS> hal::blocking::spi::Transfer<$WORD> for $Spi<$SPIX, PINS, $WORD> {
+            type Error = Error;
+
+            fn transfer<'w>(&mut self, words: &'w mut [$WORD]) -> Result<&'w [$WORD], Error> {
+                let threshold = usize::from(self.threshold);
+                let result = words
+                    .chunks_mut(MAX_TSIZE)
+                    .try_for_each(|chunk| transfer_chunk(&self.spi, chunk, threshold));
+                start(&self.spi, 0);
+
+                result.map(move |_| &*words)
+            }
+        }
+
+        impl<PINS> hal::blocking::spi::Write<$WORD> for $Spi<$SPIX, PINS, $WORD> {
+            type Error = Error;
+
+            fn write(&mut self, words: &[$WORD]) -> Result<(), Error> {
+                let threshold = usize::from(self.threshold);
+                let result = words
+                    .chunks(MAX_TSIZE)
+                    .try_for_each(|chunk| write_chunk(&self.spi, chunk, threshold));
+                start(&self.spi, 0);
+
+                result
+            }
+        }
+        )+
+    };
+}
+
+macro_rules! spi {
+    ($($SPIX:ident: ($spiX:ident, $spiXen:ident, $spiXrst:ident, $APBX:ident, $ker_ck:ident, $fifo:expr),)+) => {
+        $(
+            impl<SCK, MISO, MOSI> Spi<$SPIX, (SCK, MISO, MOSI)> {
+                /// Creates an SPI master exchanging 8 bit frames, MSB first
+                ///
+                /// The baud rate is the highest one up to `freq` that can be derived from
+                /// the kernel clock selected with `CCIPR`. Fails if that clock is not running,
+                /// which is the case for SPI1 to SPI3 unless pll1_q_ck is enabled, or if
+                /// `freq` can't be reached with a prescaler of up to 256.
+                pub fn $spiX<F>(
+                    spi: $SPIX,
+                    pins: (SCK, MISO, MOSI),
+                    mode: Mode,
+                    freq: F,
+                    clocks: Clocks,
+                    apb: &mut $APBX,
+                ) -> Result<Self, Error>
+                where
+                    F: Into<Hertz>,
+                    SCK: SckPin<$SPIX>,
+                    MISO: MisoPin<$SPIX>,
+                    MOSI: MosiPin<$SPIX>,
+                {
+                    let ker_ck = clocks.$ker_ck().ok_or(Error::KernelClock)?;
+
+                    apb.enr().modify(|_, w| w.$spiXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$spiXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$spiXrst().clear_bit());
+
+                    let mbr = prescaler(ker_ck, freq.into())?;
+                    spi.cfg1.write(|w| unsafe {
+                        w.mbr().bits(mbr).dsize().bits(7).fthvl().bits(0)
+                    });
+
+                    // NSS is managed by software and kept high internally, so the
+                    // peripheral never flags a mode fault. AFCNTR keeps SCK and MOSI
+                    // driven while the peripheral is disabled between transfers.
+                    spi.cr1.write(|w| w.ssi().set_bit());
+                    spi.cfg2.write(|w| {
+                        w.cpha()
+                            .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                            .cpol()
+                            .bit(mode.polarity == Polarity::IdleHigh)
+                            .lsbfrst()
+                            .clear_bit()
+                            .master()
+                            .set_bit()
+                            .ssm()
+                            .set_bit()
+                            .afcntr()
+                            .set_bit()
+                    });
+
+                    start(&spi, 0);
+
+                    Ok(Spi { spi, pins, threshold: 1, _word: PhantomData })
+                }
+            }
+
+            impl<PINS, WORD: Word> Spi<$SPIX, PINS, WORD> {
+                /// Releases the SPI peripheral and associated pins
+                pub fn free(self) -> ($SPIX, PINS) {
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    (self.spi, self.pins)
+                }
+
+                /// Changes the size of the frames to `bits`, from 4 to 32
+                ///
+                /// `W` is the data type the frames are exchanged as. Panics if the FIFO
+                /// can't hold as many frames of this size as the FIFO threshold.
+                pub fn frame_size<W: Word>(self, bits: u8) -> Spi<$SPIX, PINS, W> {
+                    set_frame_size::<W>(&self.spi, bits, $fifo);
+
+                    Spi { spi: self.spi, pins: self.pins, threshold: self.threshold, _word: PhantomData }
+                }
+
+                /// Sets the number of frames, from 1 to 16, the FIFOs are filled and emptied
+                /// with at once during blocking transfers
+                ///
+                /// Panics if the FIFO is too small to hold them, it holds 16 bytes on SPI1
+                /// to SPI3 and 8 bytes on SPI4 to SPI6.
+                pub fn fifo_threshold(&mut self, frames: u8) {
+                    set_fifo_threshold(&self.spi, frames, $fifo);
+                    self.threshold = frames;
+                }
+
+                /// Appends a CRC of `bits`, from 4 to 32, with `polynomial` to every
+                /// blocking transfer
+                pub fn enable_crc(&mut self, polynomial: u32, bits: u8) {
+                    set_crc(&self.spi, Some((polynomial, bits)));
+                }
+
+                /// Stops appending a CRC to the blocking transfers
+                pub fn disable_crc(&mut self) {
+                    set_crc(&self.spi, None);
+                }
+            }
+
+            impl<SCK, MISO, MOSI, WORD: Word> Spi<$SPIX, (SCK, MISO, MOSI), WORD> {
+                /// Lets the peripheral drive its hardware NSS output
+                ///
+                /// NSS is active from the start to the end of every transfer, or pulses
+                /// between the frames with `config.pulse`.
+                pub fn with_nss<NSS>(
+                    self,
+                    nss: NSS,
+                    config: NssConfig,
+                ) -> Spi<$SPIX, (SCK, MISO, MOSI, NSS), WORD>
+                where
+                    NSS: NssPin<$SPIX>,
+                {
+                    assert!(config.mssi <= 15 && config.midi <= 15);
+
+                    reconfigure(&self.spi, |spi| {
+                        spi.cfg2.modify(|_, w| unsafe {
+                            w.ssm()
+                                .clear_bit()
+                                .ssoe()
+                                .set_bit()
+                                .ssom()
+                                .bit(config.pulse)
+                                .ssiop()
+                                .bit(config.active_high)
+                                .mssi()
+                                .bits(config.mssi)
+                                .midi()
+                                .bits(config.midi)
+                        })
+                    });
+
+                    let (sck, miso, mosi) = self.pins;
+                    Spi {
+                        spi: self.spi,
+                        pins: (sck, miso, mosi, nss),
+                        threshold: self.threshold,
+                        _word: PhantomData,
+                    }
+                }
+            }
+
+            impl<SCK, MISO, MOSI, NSS, WORD: Word> Spi<$SPIX, (SCK, MISO, MOSI, NSS), WORD> {
+                /// Selects the frame format, the TI format requires the hardware NSS output
+                pub fn set_frame_format(&mut self, format: FrameFormat) {
+                    set_frame_format(&self.spi, format);
+                }
+            }
+
+            impl<SCK, MISO, MOSI, NSS> SpiSlave<$SPIX, (SCK, MISO, MOSI, NSS)> {
+                /// Creates an SPI slave exchanging 8 bit frames, MSB first
+                ///
+                /// The slave is selected while the NSS input is low. Until the first frame
+                /// is written, a master clocking frames receives zeros.
+                pub fn $spiX(
+                    spi: $SPIX,
+                    pins: (SCK, MISO, MOSI, NSS),
+                    mode: Mode,
+                    apb: &mut $APBX,
+                ) -> Self
+                where
+                    SCK: SckPin<$SPIX>,
+                    MISO: MisoPin<$SPIX>,
+                    MOSI: MosiPin<$SPIX>,
+                    NSS: NssPin<$SPIX>,
+                {
+                    apb.enr().modify(|_, w| w.$spiXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$spiXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$spiXrst().clear_bit());
+
+                    // Underruns are detected at the start of a frame and answered with the
+                    // pattern in UDRDR
+                    spi.cfg1.write(|w| unsafe {
+                        w.dsize().bits(7).fthvl().bits(0).udrdet().bits(0b00).udrcfg().bits(0b00)
+                    });
+                    spi.cfg2.write(|w| {
+                        w.cpha()
+                            .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                            .cpol()
+                            .bit(mode.polarity == Polarity::IdleHigh)
+                            .lsbfrst()
+                            .clear_bit()
+                            .master()
+                            .clear_bit()
+                            .ssm()
+                            .clear_bit()
+                    });
+
+                    start(&spi, 0);
+
+                    SpiSlave { spi, pins, threshold: 1, _word: PhantomData }
+                }
+            }
+
+            impl<PINS, WORD: Word> SpiSlave<$SPIX, PINS, WORD> {
+                /// Releases the SPI peripheral and associated pins
+                pub fn free(self) -> ($SPIX, PINS) {
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    (self.spi, self.pins)
+                }
+
+                /// Changes the size of the frames to `bits`, from 4 to 32
+                ///
+                /// `W` is the data type the frames are exchanged as. Panics if the FIFO
+                /// can't hold as many frames of this size as the FIFO threshold.
+                pub fn frame_size<W: Word>(self, bits: u8) -> SpiSlave<$SPIX, PINS, W> {
+                    set_frame_size::<W>(&self.spi, bits, $fifo);
+
+                    SpiSlave { spi: self.spi, pins: self.pins, threshold: self.threshold, _word: PhantomData }
+                }
+
+                /// Sets the number of frames, from 1 to 16, the FIFOs are filled and emptied
+                /// with at once during blocking transfers
+                ///
+                /// Panics if the FIFO is too small to hold them, it holds 16 bytes on SPI1
+                /// to SPI3 and 8 bytes on SPI4 to SPI6.
+                pub fn fifo_threshold(&mut self, frames: u8) {
+                    set_fifo_threshold(&self.spi, frames, $fifo);
+                    self.threshold = frames;
+                }
+
+                /// Expects a CRC of `bits`, from 4 to 32, with `polynomial` at the end of
+                /// every blocking transfer and sends one as well
+                pub fn enable_crc(&mut self, polynomial: u32, bits: u8) {
+                    set_crc(&self.spi, Some((polynomial, bits)));
+                }
+
+                /// Stops checking and sending a CRC
+                pub fn disable_crc(&mut self) {
+                    set_crc(&self.spi, None);
+                }
+
+                /// Selects the frame format
+                pub fn set_frame_format(&mut self, format: FrameFormat) {
+                    set_frame_format(&self.spi, format);
+                }
+
+                /// Selects the data sent on an underrun, which is reported as `Error::Underrun`
+                pub fn set_underrun_data(&mut self, data: UnderrunData) {
+                    reconfigure(&self.spi, |spi| {
+                        let udrcfg = match data {
+                            UnderrunData::Pattern(pattern) => {
+                                spi.udrdr.write(|w| unsafe { w.bits(pattern) });
+                                0b00
+                            }
+                            UnderrunData::LastReceived => 0b01,
+                            UnderrunData::LastTransmitted => 0b10,
+                        };
+                        spi.cfg1.modify(|_, w| unsafe { w.udrcfg().bits(udrcfg) });
+                    });
+                }
+            }
+
+            impl<PINS, WORD: Word> FullDuplex<WORD> for SpiSlave<$SPIX, PINS, WORD> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<WORD, Error> {
+                    let sr = self.spi.sr.read();
+                    check_errors(&self.spi, &sr).map_err(nb::Error::Other)?;
+
+                    if rx_available(&sr) {
+                        Ok(read_frame(&self.spi))
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                fn send(&mut self, word: WORD) -> nb::Result<(), Error> {
+                    let sr = self.spi.sr.read();
+                    check_errors(&self.spi, &sr).map_err(nb::Error::Other)?;
+
+                    if sr.txp().bit_is_set() {
+                        write_frame(&self.spi, word);
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl<PINS, WORD: Word> FullDuplex<WORD> for Spi<$SPIX, PINS, WORD> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<WORD, Error> {
+                    let sr = self.spi.sr.read();
+                    check_errors(&self.spi, &sr).map_err(nb::Error::Other)?;
+
+                    if rx_available(&sr) {
+                        Ok(read_frame(&self.spi))
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                fn send(&mut self, word: WORD) -> nb::Result<(), Error> {
+                    let sr = self.spi.sr.read();
+                    check_errors(&self.spi, &sr).map_err(nb::Error::Other)?;
+
+                    if sr.txp().bit_is_set() {
+                        write_frame(&self.spi, word);
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            spi_blocking!(Spi, $SPIX: [u8, u16, u32]);
+            spi_blocking!(SpiSlave, $SPIX: [u8, u16, u32]);
+        )+
+    }
+}
+
+spi!(
+    SPI1: (spi1, spi1en, spi1rst, APB2, spi123_ker_ck, 16),
+    SPI2: (spi2, spi2en, spi2rst, APB1L, spi123_ker_ck, 16),
+    SPI3: (spi3, spi3en, spi3rst, APB1L, spi123_ker_ck, 16),
+    SPI4: (spi4, spi4en, spi4rst, APB2, spi45_ker_ck, 8),
+    SPI5: (spi5, spi5en, spi5rst, APB2, spi45_ker_ck, 8),
+    SPI6: (spi6, spi6en, spi6rst, APB4, spi6_ker_ck, 8),
+);
+
+macro_rules! spi_dma {
+    ($($SPIX:ident: ($rx_request:expr, $tx_request:expr),)+) => {
+        $(
+            impl<PINS, WORD: Word> Spi<$SPIX, PINS, WORD> {
+                /// Sends `words` with the DMA stream `tx`, discarding the received frames
+                ///
+                /// The transfer holds at most 65535 frames, the buffer has to be
+                /// accessible by DMA1 and DMA2.
+                pub fn write_dma<TX>(
+                    self,
+                    words: &'static [WORD],
+                    mut tx: TX,
+                ) -> Transfer<$SPIX, PINS, WORD, &'static [WORD], TX>
+                where
+                    TX: Stream,
+                {
+                    assert!(!words.is_empty() && words.len() <= MAX_TSIZE);
+
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    // NOTE(unsafe) the transfer owns the 'static buffer until the stream is
+                    // disabled
+                    unsafe {
+                        tx.configure(&dma_config::<WORD>(
+                            $tx_request,
+                            dma::Direction::MemoryToPeripheral,
+                            &self.spi.txdr as *const _ as u32,
+                            words.as_ptr() as u32,
+                            words.len(),
+                        ));
+                    }
+                    tx.enable();
+                    self.spi.cfg1.modify(|_, w| w.txdmaen().set_bit());
+                    start_dma(&self.spi, words.len() as u16, COMM_TRANSMITTER);
+
+                    Transfer { spi: self, buffers: words, streams: tx, result: None, callback: None }
+                }
+
+                /// Fills `buffer` with the DMA stream `rx`, the master clocks the frames
+                /// without driving MOSI
+                ///
+                /// The transfer holds at most 65535 frames, the buffer has to be
+                /// accessible by DMA1 and DMA2.
+                pub fn read_dma<RX>(
+                    self,
+                    buffer: &'static mut [WORD],
+                    mut rx: RX,
+                ) -> Transfer<$SPIX, PINS, WORD, &'static mut [WORD], RX>
+                where
+                    RX: Stream,
+                {
+                    assert!(!buffer.is_empty() && buffer.len() <= MAX_TSIZE);
+
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cfg1.modify(|_, w| w.rxdmaen().set_bit());
+                    // NOTE(unsafe) the transfer owns the 'static buffer until the stream is
+                    // disabled
+                    unsafe {
+                        rx.configure(&dma_config::<WORD>(
+                            $rx_request,
+                            dma::Direction::PeripheralToMemory,
+                            &self.spi.rxdr as *const _ as u32,
+                            buffer.as_mut_ptr() as u32,
+                            buffer.len(),
+                        ));
+                    }
+                    rx.enable();
+                    start_dma(&self.spi, buffer.len() as u16, COMM_RECEIVER);
+
+                    Transfer { spi: self, buffers: buffer, streams: rx, result: None, callback: None }
+                }
+
+                /// Exchanges `words` with the frames received into `buffer`, using the DMA
+                /// streams `tx` and `rx`
+                ///
+                /// Both buffers have the same length of at most 65535 frames and have
+                /// to be accessible by DMA1 and DMA2.
+                pub fn transfer_dma<TX, RX>(
+                    self,
+                    words: &'static [WORD],
+                    buffer: &'static mut [WORD],
+                    (mut tx, mut rx): (TX, RX),
+                ) -> Transfer<$SPIX, PINS, WORD, (&'static [WORD], &'static mut [WORD]), (TX, RX)>
+                where
+                    TX: Stream,
+                    RX: Stream,
+                {
+                    assert!(!words.is_empty() && words.len() <= MAX_TSIZE);
+                    assert_eq!(words.len(), buffer.len());
+
+                    // The RX requests are enabled before and the TX requests after the
+                    // streams, as required by the reference manual
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cfg1.modify(|_, w| w.rxdmaen().set_bit());
+                    // NOTE(unsafe) the transfer owns the 'static buffers until the streams are
+                    // disabled
+                    unsafe {
+                        rx.configure(&dma_config::<WORD>(
+                            $rx_request,
+                            dma::Direction::PeripheralToMemory,
+                            &self.spi.rxdr as *const _ as u32,
+                            buffer.as_mut_ptr() as u32,
+                            buffer.len(),
+                        ));
+                        tx.configure(&dma_config::<WORD>(
+                            $tx_request,
+                            dma::Direction::MemoryToPeripheral,
+                            &self.spi.txdr as *const _ as u32,
+                            words.as_ptr() as u32,
+                            words.len(),
+                        ));
+                    }
+                    rx.enable();
+                    tx.enable();
+                    self.spi.cfg1.modify(|_, w| w.txdmaen().set_bit());
+                    start_dma(&self.spi, words.len() as u16, COMM_FULL_DUPLEX);
+
+                    Transfer {
+                        spi: self,
+                        buffers: (words, buffer),
+                        streams: (tx, rx),
+                        result: None,
+                        callback: None,
+                    }
+                }
+
+                /// Receives frames continuously into the two `buffers` with the DMA stream
+                /// `rx` in double buffer mode
+                ///
+                /// Both buffers have the same length of at most 65535 frames and have to
+                /// be accessible by DMA1 and DMA2. Listening for the `TransferComplete` event
+                /// of the stream signals every filled buffer.
+                pub fn read_circular_dma<RX>(
+                    self,
+                    buffers: (&'static mut [WORD], &'static mut [WORD]),
+                    mut rx: RX,
+                ) -> CircularRead<$SPIX, PINS, WORD, RX>
+                where
+                    RX: Stream,
+                {
+                    let (first, second) = buffers;
+                    assert!(!first.is_empty() && first.len() <= MAX_TSIZE);
+                    assert_eq!(first.len(), second.len());
+
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cfg1.modify(|_, w| w.rxdmaen().set_bit());
+                    // NOTE(unsafe) the reception owns the 'static buffers until the stream is
+                    // disabled
+                    unsafe {
+                        rx.configure(&dma::Config {
+                            memory1: Some(second.as_mut_ptr() as u32),
+                            ..dma_config::<WORD>(
+                                $rx_request,
+                                dma::Direction::PeripheralToMemory,
+                                &self.spi.rxdr as *const _ as u32,
+                                first.as_mut_ptr() as u32,
+                                first.len(),
+                            )
+                        });
+                    }
+                    rx.enable();
+                    // An endless transfer, the master clocks until it is suspended
+                    start_dma(&self.spi, 0, COMM_RECEIVER);
+
+                    CircularRead { spi: self, buffers: (first, second), stream: rx }
+                }
+            }
+
+            impl<PINS, WORD, BUFFERS, STREAMS> Transfer<$SPIX, PINS, WORD, BUFFERS, STREAMS>
+            where
+                STREAMS: TransferStreams,
+            {
+                /// Advances the transfer, returns its result once it is done
+                ///
+                /// The end of the transfer is acknowledged, and the callback called, only
+                /// once. Afterwards the result is returned again.
+                pub fn poll(&mut self) -> nb::Result<(), Error> {
+                    if let Some(result) = self.result {
+                        return result.map_err(nb::Error::Other);
+                    }
+
+                    let spi = &self.spi.spi;
+                    let sr = spi.sr.read();
+                    // The last frames may still be on their way out of the RX FIFO at EOT
+                    let result = if self.streams.has_error() {
+                        Err(Error::Dma)
+                    } else if let Err(error) = check_errors(spi, &sr) {
+                        Err(error)
+                    } else if sr.eot().bit_is_set() && self.streams.is_complete() {
+                        Ok(())
+                    } else {
+                        return Err(nb::Error::WouldBlock);
+                    };
+
+                    self.streams.disable();
+                    stop_dma(spi);
+                    self.result = Some(result);
+                    self.notify();
+
+                    result.map_err(nb::Error::Other)
+                }
+
+                /// Blocks until the transfer is done
+                pub fn wait(&mut self) -> Result<(), Error> {
+                    block!(self.poll())
+                }
+            }
+
+            impl<PINS, WORD, RX> CircularRead<$SPIX, PINS, WORD, RX>
+            where
+                RX: Stream,
+            {
+                /// Stops the reception, releases the SPI peripheral, the buffers and the DMA
+                /// stream
+                pub fn stop(mut self) -> (
+                    Spi<$SPIX, PINS, WORD>,
+                    (&'static mut [WORD], &'static mut [WORD]),
+                    RX,
+                ) {
+                    stop_dma(&self.spi.spi);
+                    self.stream.disable();
+
+                    (self.spi, self.buffers, self.stream)
+                }
+            }
+        )+
+    };
+}
+
+// DMAMUX1 request lines, SPI6 is only connected to the BDMA
+spi_dma!(
+    SPI1: (37, 38),
+    SPI2: (39, 40),
+    SPI3: (61, 62),
+    SPI4: (83, 84),
+    SPI5: (85, 86),
+);